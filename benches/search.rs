@@ -0,0 +1,104 @@
+//! Search throughput over a synthetic 1M-entry history.
+//!
+//! Run with `cargo bench --bench search`.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use ihistory::history::HistoryEntry;
+use ihistory::search::{SearchEngine, SearchMode};
+
+const ENTRIES: usize = 1_000_000;
+
+const PROGRAMS: &[&str] = &[
+    "git", "cargo", "kubectl", "docker", "npm", "ssh", "grep", "find", "vim", "make",
+];
+const ARGS: &[&str] = &[
+    "status",
+    "push origin main",
+    "build --release",
+    "get pods -n kube-system",
+    "run --rm -it ubuntu:22.04 bash",
+    "install --save-dev typescript",
+    "deploy@prod-web-03.example.com",
+    "-rn 'TODO' src/",
+    ". -name '*.rs' -mtime -7",
+    "~/.config/ihistory/config.toml",
+    "test -- --nocapture",
+    "logs -f deployment/api",
+];
+
+/// Deterministic, varied commands: a program, two argument fragments and a
+/// number, so that no two entries are quite alike.
+fn history(len: usize) -> Vec<HistoryEntry> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as usize
+    };
+    (0..len)
+        .map(|i| HistoryEntry {
+            command: format!(
+                "{} {} {} {}",
+                PROGRAMS[next() % PROGRAMS.len()],
+                ARGS[next() % ARGS.len()],
+                ARGS[next() % ARGS.len()],
+                next() % 10_000,
+            ),
+            timestamp: Some(1_700_000_000 - i as i64 * 60),
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn search(c: &mut Criterion) {
+    let entries = history(ENTRIES);
+    let mut group = c.benchmark_group("search_1m");
+    group.sample_size(10);
+
+    let engine = SearchEngine::new();
+    for query in [
+        "",
+        "g",
+        "git",
+        "kub pods",
+        "^docker 'ubuntu !bash",
+        "push | deploy",
+    ] {
+        group.bench_function(format!("fuzzy {:?}", query), |b| {
+            b.iter(|| engine.search(black_box(&entries), black_box(query)))
+        });
+    }
+
+    for (mode, query) in [
+        (SearchMode::Substring, "origin main"),
+        (SearchMode::Prefix, "cargo build"),
+        (SearchMode::WordBoundary, "ku ge po"),
+        (SearchMode::Regex, r"^git .*\d{4}$"),
+    ] {
+        let mut engine = SearchEngine::new();
+        engine.set_mode(mode);
+        group.bench_function(format!("{} {:?}", mode.label(), query), |b| {
+            b.iter(|| engine.search(black_box(&entries), black_box(query)))
+        });
+    }
+
+    // Typing one more character: narrowing the previous results against
+    // searching everything again.
+    let previous = engine.search(&entries, "git pu").unwrap();
+    group.bench_function("refine \"git pu\" -> \"git pus\"", |b| {
+        b.iter_batched(
+            || previous.clone(),
+            |previous| engine.refine(black_box(&entries), &previous, black_box("git pus")),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("search \"git pus\"", |b| {
+        b.iter(|| engine.search(black_box(&entries), black_box("git pus")))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
@@ -36,3 +36,56 @@ if [[ $- == *i* ]]; then
     bind -x '"\C-r": ih-widget' 2>/dev/null
   fi
 fi
+
+# Record each command's directory, exit status and duration in ihistory's own
+# log. Export IHISTORY_NO_RECORD=1 before the eval line to opt out. bash has
+# no preexec hook, so the start of a command is caught with a DEBUG trap; an
+# existing DEBUG trap is left alone and recording stays off.
+if [[ $- == *i* && -z "$IHISTORY_NO_RECORD" && -z "$(trap -p DEBUG)" ]]; then
+  _ihistory_session="$$.$RANDOM"
+  _ihistory_armed=""
+  _ihistory_start=""
+  _ihistory_last=""
+
+  _ihistory_now() {
+    if [[ -n "$EPOCHREALTIME" ]]; then
+      _ihistory_time="$EPOCHREALTIME"
+    else
+      printf -v _ihistory_time '%(%s)T' -1
+    fi
+  }
+
+  # Runs before every simple command. Only the first one after a prompt is
+  # the start of what the user typed; PROMPT_COMMAND re-arms it last.
+  _ihistory_preexec() {
+    [[ -n "$_ihistory_armed" && -z "$COMP_LINE" ]] || return 0
+    _ihistory_armed=""
+    _ihistory_now
+    _ihistory_start="$_ihistory_time"
+    _ihistory_cwd="$PWD"
+  }
+
+  _ihistory_precmd() {
+    local ret=$? start="$_ihistory_start" entry number
+    [[ -n "$start" ]] || return 0
+    _ihistory_start=""
+    entry="$(HISTTIMEFORMAT='' builtin history 1)"
+    # "  123  command": an unchanged number means nothing new was run
+    # (an empty line, or a command history chose not to keep).
+    entry="${entry#"${entry%%[![:space:]]*}"}"
+    number="${entry%%[[:space:]]*}"
+    [[ "$number" != "$_ihistory_last" ]] || return 0
+    _ihistory_last="$number"
+    entry="${entry#"$number"}"
+    entry="${entry#"${entry%%[![:space:]]*}"}"
+    (command ihistory __record --start "$start" --exit "$ret" \
+      --cwd "$_ihistory_cwd" --session "$_ihistory_session" -- "$entry" &)
+  }
+
+  _ihistory_arm() {
+    _ihistory_armed=1
+  }
+
+  trap '_ihistory_preexec' DEBUG
+  PROMPT_COMMAND="_ihistory_precmd${PROMPT_COMMAND:+; $PROMPT_COMMAND}; _ihistory_arm"
+fi
@@ -0,0 +1,57 @@
+# ihistory — fish integration
+# Usage: ihistory --init fish | source
+
+function ih
+    set -l selected (command ihistory $argv)
+    set -l ret $status
+    # Command substitution splits on newlines; put multi-line entries back.
+    set selected (string join \n -- $selected)
+    test -n "$selected"; or return 0
+    if test $ret -eq 10
+        eval $selected
+    else
+        printf '%s\n' $selected
+    end
+end
+
+# Key bindings are only meaningful in an interactive shell.
+if status is-interactive
+    function __ihistory_widget
+        set -l selected (command ihistory)
+        set -l ret $status
+        set selected (string join \n -- $selected)
+        if test -n "$selected"; and contains -- $ret 0 10
+            commandline --replace -- $selected
+            commandline --cursor (string length -- $selected)
+        end
+        commandline --function repaint
+        if test -n "$selected"; and test $ret -eq 10
+            commandline --function execute
+        end
+    end
+
+    # Ctrl+R opens the search in the default and vi insert modes. Set
+    # IHISTORY_NO_BINDKEY before sourcing to keep your existing binding.
+    if not set -q IHISTORY_NO_BINDKEY
+        bind \cr __ihistory_widget
+        bind -M insert \cr __ihistory_widget
+    end
+end
+
+# Record each command's directory, exit status and duration in ihistory's own
+# log. Set IHISTORY_NO_RECORD before sourcing to opt out.
+if status is-interactive; and not set -q IHISTORY_NO_RECORD
+    set -g __ihistory_session $fish_pid.(random)
+
+    function __ihistory_preexec --on-event fish_preexec
+        set -g __ihistory_cwd $PWD
+    end
+
+    function __ihistory_postexec --on-event fish_postexec
+        set -l ret $status
+        string length -q -- (string trim -- $argv[1]); or return
+        command ihistory __record --duration $CMD_DURATION --exit $ret \
+            --cwd $__ihistory_cwd --session $__ihistory_session -- $argv[1] &
+        disown $last_pid 2>/dev/null
+    end
+end
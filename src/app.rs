@@ -1,164 +1,562 @@
 use std::fs::File;
 use std::io;
 use std::path::PathBuf;
+use std::sync::mpsc::TryRecvError;
+use std::time::Duration;
 
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    event::{
+        self, DisableBracketedPaste, EnableBracketedPaste, Event, KeyCode, KeyEventKind,
+        KeyModifiers,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        self, disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+    },
 };
-use ratatui::{backend::CrosstermBackend, widgets::ListState, Terminal};
+use ratatui::{backend::CrosstermBackend, widgets::ListState, Terminal, TerminalOptions, Viewport};
 
-use crate::history::{detect_history_file, hide_entry, load_history, HistoryEntry};
-use crate::search::{SearchEngine, SearchResult};
+use crate::editor::LineEditor;
+use crate::inline;
 use crate::ui::UI;
 use crate::Args;
+use ihistory::config::{self, Config, Join, Keymap};
+use ihistory::history::{
+    detect_history_file, load_in_background, History, HistoryFormat, HistoryLoader, HistorySource,
+};
+use ihistory::keys::{Action, Bindings, KeyChord, Lookup};
+use ihistory::search::{find_repo_root, SearchEngine, SearchResult, SearchScope};
+use ihistory::store;
+use ihistory::theme::{self, ColorDepth, Theme};
 
 pub const EXIT_CODE_EXECUTE: i32 = 10;
 
+/// How long the event loop waits for a key before checking on the history
+/// loader.
+const LOAD_POLL_INTERVAL: Duration = Duration::from_millis(30);
+
+/// Rows moved by `PageUp`/`PageDown`; half of it for vi's `Ctrl+D`/`Ctrl+U`.
+const PAGE: usize = 20;
+
+/// The bindings in force: the emacs keymap's, or vi's for each mode.
+pub struct Keys {
+    insert: Bindings,
+    normal: Bindings,
+    vi: bool,
+}
+
+impl Keys {
+    /// The defaults for `keymap`, with the config's bindings on top.
+    pub fn new(keymap: Keymap, config: &Config) -> Self {
+        let insert = match keymap {
+            Keymap::Emacs => Bindings::emacs(),
+            Keymap::Vi => Bindings::vi_insert(),
+        };
+        Self {
+            insert: insert.with_overrides(&config.keys),
+            normal: Bindings::vi_normal().with_overrides(&config.normal_keys),
+            vi: keymap == Keymap::Vi,
+        }
+    }
+
+    fn bindings(&self, vi_mode: Option<ViMode>) -> &Bindings {
+        match vi_mode {
+            Some(ViMode::Normal) => &self.normal,
+            _ => &self.insert,
+        }
+    }
+}
+
+/// Where keys go under the vi keymap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViMode {
+    /// Keys edit the query, as with the emacs keymap.
+    Insert,
+    /// Keys move through and act on the results.
+    Normal,
+}
+
 pub struct App {
-    entries: Vec<HistoryEntry>,
-    query: String,
+    history: History,
+    /// Set while history is still arriving from the background thread.
+    loader: Option<HistoryLoader>,
+    query: LineEditor,
     results: Vec<SearchResult>,
+    /// Entries marked for a batch action, as indices into the history, in
+    /// the order they were marked.
+    marked: Vec<usize>,
+    join: Join,
+    keys: Keys,
+    /// `None` under the emacs keymap.
+    vi_mode: Option<ViMode>,
+    /// The start of a key sequence still being typed, like vi's `gg`.
+    pending_keys: Vec<KeyChord>,
+    /// The query `results` answer, or `None` once they are stale and the
+    /// next search has to start from every entry.
+    searched_query: Option<String>,
     selected: usize,
     scroll_offset: usize,
+    /// Rows of the previewed command scrolled past, and the entry that is.
+    /// Another entry starts from the top.
+    preview_scroll: usize,
+    previewed: Option<usize>,
     search_engine: SearchEngine,
     ui: UI,
     list_state: ListState,
     should_quit: bool,
     selected_command: Option<String>,
     execute_immediately: bool,
-    history_path: PathBuf,
     status_message: Option<String>,
+    cwd: Option<PathBuf>,
+}
+
+/// The directory the shell is in. `$PWD` keeps the path as the user typed
+/// it, symlinks and all, which is also what the shell hooks record; the
+/// resolved path is only a fallback.
+fn current_dir() -> Option<PathBuf> {
+    let resolved = std::env::current_dir().ok()?;
+    match std::env::var_os("PWD").map(PathBuf::from) {
+        Some(pwd) if pwd.canonicalize().ok() == resolved.canonicalize().ok() => Some(pwd),
+        _ => Some(resolved),
+    }
 }
 
 impl App {
     pub fn new(
-        entries: Vec<HistoryEntry>,
-        history_path: PathBuf,
+        history: History,
+        loader: Option<HistoryLoader>,
         initial_query: Option<String>,
+        search_engine: SearchEngine,
+        ui: UI,
+        join: Join,
+        keys: Keys,
     ) -> Self {
-        let search_engine = SearchEngine::new();
-        let query = initial_query.unwrap_or_default();
-        let results = search_engine.search(&entries, &query);
-
-        Self {
-            entries,
-            query,
-            results,
+        let mut app = Self {
+            history,
+            loader,
+            query: LineEditor::new(initial_query.unwrap_or_default()),
+            results: Vec::new(),
+            marked: Vec::new(),
+            join,
+            vi_mode: keys.vi.then_some(ViMode::Insert),
+            keys,
+            pending_keys: Vec::new(),
+            searched_query: None,
             selected: 0,
             scroll_offset: 0,
+            preview_scroll: 0,
+            previewed: None,
             search_engine,
-            ui: UI::new(),
+            ui,
             list_state: ListState::default(),
             should_quit: false,
             selected_command: None,
             execute_immediately: false,
-            history_path,
             status_message: None,
-        }
+            cwd: current_dir(),
+        };
+        app.update_search();
+        app
+    }
+
+    fn cycle_mode(&mut self) {
+        let next = self.search_engine.mode().next();
+        self.search_engine.set_mode(next);
+        self.reset_search();
     }
 
+    /// Global → this directory → this repository → global. The repository
+    /// step is skipped outside a git checkout.
+    fn cycle_scope(&mut self) {
+        let Some(cwd) = self.cwd.clone() else {
+            self.status_message = Some("Current directory is unavailable".to_string());
+            return;
+        };
+
+        let next = match self.search_engine.scope() {
+            SearchScope::Global => SearchScope::Directory(cwd),
+            SearchScope::Directory(_) => match find_repo_root(&cwd) {
+                Some(root) => SearchScope::Repository(root),
+                None => SearchScope::Global,
+            },
+            SearchScope::Repository(_) => SearchScope::Global,
+        };
+        self.search_engine.set_scope(next);
+        self.selected = 0;
+        self.reset_search();
+    }
+
+    /// Re-runs the search, narrowing the previous results when the query
+    /// only got more specific. A query that does not compile (a regex still
+    /// being typed) keeps the previous results on screen and reports why.
     fn update_search(&mut self) {
-        self.results = self.search_engine.search(&self.entries, &self.query);
+        let results = match &self.searched_query {
+            Some(previous) if self.search_engine.refines(previous, self.query.text()) => self
+                .search_engine
+                .refine(self.history.entries(), &self.results, self.query.text()),
+            _ => self
+                .search_engine
+                .search(self.history.entries(), self.query.text()),
+        };
+        match results {
+            Ok(results) => {
+                self.results = results;
+                self.searched_query = Some(self.query.text().to_string());
+            }
+            Err(e) => self.status_message = Some(e.to_string()),
+        }
         if self.selected >= self.results.len() {
             self.selected = self.results.len().saturating_sub(1);
         }
         self.scroll_offset = 0;
     }
 
-    fn hide_selected(&mut self) {
+    /// Searches from scratch, for when the entries or the engine's settings
+    /// changed under the current results.
+    fn reset_search(&mut self) {
+        self.searched_query = None;
+        self.update_search();
+    }
+
+    /// Marks or unmarks the selected result and moves on to the next one.
+    fn toggle_mark(&mut self) {
         let Some(result) = self.results.get(self.selected) else {
             return;
         };
+        match self.marked.iter().position(|&i| i == result.index) {
+            Some(position) => {
+                self.marked.remove(position);
+            }
+            None => self.marked.push(result.index),
+        }
+        if self.selected + 1 < self.results.len() {
+            self.selected += 1;
+        }
+    }
+
+    /// Hides every marked entry, or the selected one when none are marked.
+    fn hide_selected(&mut self) {
+        let mut targets = if self.marked.is_empty() {
+            match self.results.get(self.selected) {
+                Some(result) => vec![result.index],
+                None => return,
+            }
+        } else {
+            self.marked.clone()
+        };
 
-        let command = result.entry.command.clone();
         let prev_selected = self.selected;
 
-        if let Err(e) = hide_entry(&self.history_path, &result.entry) {
-            self.status_message = Some(format!("Hide failed: {}", e));
-            return;
+        // Back to front, so that each removal leaves the indices still to
+        // go untouched. A mark is only dropped once its entry is hidden, so
+        // after a failure the rest stay marked for another try.
+        targets.sort_unstable_by(|a, b| b.cmp(a));
+        for index in targets {
+            if let Err(e) = self.history.hide(index) {
+                self.status_message = Some(format!("Hide failed: {}", e));
+                break;
+            }
+            self.marked.retain(|&marked| marked != index);
         }
 
-        self.entries.retain(|e| e.command != command);
-        self.results = self.search_engine.search(&self.entries, &self.query);
+        self.reset_search();
         self.selected = prev_selected.min(self.results.len().saturating_sub(1));
     }
 
+    /// Takes in whatever history the loader sent since the last call and
+    /// says whether anything changed. History that turns out to be empty
+    /// is an error, as there is nothing to search.
+    fn receive_history(&mut self) -> io::Result<bool> {
+        let Some(loader) = &self.loader else {
+            return Ok(false);
+        };
+
+        let loaded = self.history.entries().len();
+        let mut received = false;
+        let mut finished = false;
+        loop {
+            match loader.try_recv() {
+                Ok(runs) => {
+                    self.history.extend(runs?);
+                    received = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    finished = true;
+                    break;
+                }
+            }
+        }
+
+        if finished {
+            self.loader = None;
+            if self.history.entries().is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "No history entries found",
+                ));
+            }
+        }
+        // Without a query or scope, the results are every entry in order,
+        // and new ones only join the end.
+        let unfiltered = self.searched_query.as_deref() == Some("")
+            && self.query.text().is_empty()
+            && matches!(self.search_engine.scope(), SearchScope::Global);
+        if received && unfiltered {
+            match self
+                .search_engine
+                .search_from(self.history.entries(), loaded, "")
+            {
+                Ok(mut added) => self.results.append(&mut added),
+                Err(e) => self.status_message = Some(e.to_string()),
+            }
+        } else if received {
+            // Older runs can still rank above the selected entry, so follow
+            // it to keep it on the same row under the cursor.
+            let followed = self.results.get(self.selected).map(|result| result.index);
+            let row = self.selected.saturating_sub(self.scroll_offset);
+            self.reset_search();
+            if let Some(position) =
+                followed.and_then(|index| self.results.iter().position(|r| r.index == index))
+            {
+                self.selected = position;
+                self.scroll_offset = position.saturating_sub(row);
+            }
+        }
+        Ok(received || finished)
+    }
+
+    /// Picks the marked commands, in the order they were marked, or else
+    /// the selected one.
     fn select_command(&mut self, execute: bool) {
-        if let Some(result) = self.results.get(self.selected) {
-            self.selected_command = Some(result.entry.command.clone());
+        let entries = self.history.entries();
+        if !self.marked.is_empty() {
+            let commands: Vec<&str> = self
+                .marked
+                .iter()
+                .map(|&i| entries[i].command.as_str())
+                .collect();
+            self.selected_command = Some(commands.join(self.join.separator()));
+            self.execute_immediately = execute;
+        } else if let Some(result) = self.results.get(self.selected) {
+            self.selected_command = Some(entries[result.index].command.clone());
             self.execute_immediately = execute;
         }
         self.should_quit = true;
     }
 
+    /// Applies an edit to the query, searching again if it changed the
+    /// text rather than just moving the cursor.
+    fn edit_query(&mut self, edit: impl FnOnce(&mut LineEditor) -> bool) {
+        if edit(&mut self.query) {
+            self.update_search();
+        }
+    }
+
+    fn paste(&mut self, text: &str) {
+        self.status_message = None;
+        self.edit_query(|query| query.insert_str(text));
+    }
+
+    fn select_up(&mut self, rows: usize) {
+        self.selected = self.selected.saturating_sub(rows);
+    }
+
+    fn select_down(&mut self, rows: usize) {
+        self.selected = (self.selected + rows).min(self.results.len().saturating_sub(1));
+    }
+
     fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
         self.status_message = None;
-        match (code, modifiers) {
-            (KeyCode::Esc, _) | (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
-                self.should_quit = true;
-            }
-            (KeyCode::Char('d'), KeyModifiers::CONTROL) => self.hide_selected(),
-            (KeyCode::Enter, _) => self.select_command(false),
-            (KeyCode::Tab, _) => self.select_command(true),
-            (KeyCode::Up, _) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
-                self.selected = self.selected.saturating_sub(1);
-            }
-            (KeyCode::Down, _) | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
-                if self.selected + 1 < self.results.len() {
-                    self.selected += 1;
+        let chord = KeyChord::new(code, modifiers);
+        let mut pressed = std::mem::take(&mut self.pending_keys);
+        pressed.push(chord);
+
+        match self.keys.bindings(self.vi_mode).lookup(&pressed) {
+            Lookup::Action(action) => self.perform(action),
+            Lookup::Prefix => self.pending_keys = pressed,
+            // A sequence that went nowhere: the keys before the last one
+            // were text after all, and the last one counts on its own.
+            Lookup::Unbound if pressed.len() > 1 => {
+                for earlier in &pressed[..pressed.len() - 1] {
+                    self.type_char(*earlier);
                 }
+                self.handle_key(code, modifiers);
             }
-            (KeyCode::PageUp, _) => {
-                self.selected = self.selected.saturating_sub(20);
-            }
-            (KeyCode::PageDown, _) => {
-                self.selected = (self.selected + 20).min(self.results.len().saturating_sub(1));
-            }
-            (KeyCode::Backspace, _) => {
-                self.query.pop();
-                self.update_search();
-            }
-            (KeyCode::Char('u'), KeyModifiers::CONTROL) => {
-                self.query.clear();
-                self.update_search();
-            }
-            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
-                self.query.push(c);
-                self.update_search();
-            }
-            _ => {}
+            Lookup::Unbound => self.type_char(chord),
+        }
+    }
+
+    /// Types an unbound key into the query, unless it is not text or vi's
+    /// normal mode is on, where letters are only ever commands.
+    fn type_char(&mut self, chord: KeyChord) {
+        if self.vi_mode == Some(ViMode::Normal) {
+            return;
+        }
+        if let Some(c) = chord.typed_char() {
+            self.edit_query(|query| query.insert(c));
+        }
+    }
+
+    fn perform(&mut self, action: Action) {
+        // Arrows follow the screen: listed bottom-up, the next result is
+        // the one above.
+        let action = if self.ui.lists_upwards() {
+            action.mirrored()
+        } else {
+            action
+        };
+        match action {
+            Action::Quit => self.should_quit = true,
+            Action::Accept => self.select_command(false),
+            Action::Execute => self.select_command(true),
+            Action::Up => self.select_up(1),
+            Action::Down => self.select_down(1),
+            Action::PageUp => self.select_up(PAGE),
+            Action::PageDown => self.select_down(PAGE),
+            Action::HalfPageUp => self.select_up(PAGE / 2),
+            Action::HalfPageDown => self.select_down(PAGE / 2),
+            Action::First => self.selected = 0,
+            Action::Last => self.select_down(self.results.len()),
+            Action::Mark => self.toggle_mark(),
+            Action::Hide => self.hide_selected(),
+            Action::CycleMode => self.cycle_mode(),
+            Action::CycleScope => self.cycle_scope(),
+            Action::PreviewUp => self.preview_scroll = self.preview_scroll.saturating_sub(1),
+            Action::PreviewDown => self.preview_scroll += 1,
+            Action::Clear => self.edit_query(LineEditor::clear),
+            Action::Left => self.query.move_left(),
+            Action::Right => self.query.move_right(),
+            Action::Home => self.query.move_home(),
+            Action::End => self.query.move_end(),
+            Action::WordLeft => self.query.move_word_left(),
+            Action::WordRight => self.query.move_word_right(),
+            Action::Backspace => self.edit_query(LineEditor::backspace),
+            Action::Delete => self.edit_query(LineEditor::delete),
+            Action::DeleteWord => self.edit_query(LineEditor::delete_word_before),
+            Action::DeleteToStart => self.edit_query(LineEditor::delete_to_start),
+            Action::NormalMode if self.vi_mode.is_some() => self.vi_mode = Some(ViMode::Normal),
+            Action::InsertMode if self.vi_mode.is_some() => self.vi_mode = Some(ViMode::Insert),
+            Action::NormalMode | Action::InsertMode | Action::Unbound => {}
         }
     }
 }
 
+/// `--file` paths win over the config's `[[sources]]`, which win over the
+/// current shell's own history file. Unless `--file` is given, ihistory's own
+/// log joins in once the shell hooks have started writing it.
+fn history_sources(args: &Args, config: &Config) -> Option<Vec<HistorySource>> {
+    if !args.file.is_empty() {
+        return Some(
+            args.file
+                .iter()
+                .map(|path| HistorySource::new(path.clone(), args.format, None))
+                .collect(),
+        );
+    }
+
+    let mut sources: Vec<HistorySource> = if !config.sources.is_empty() {
+        config
+            .sources
+            .iter()
+            .map(|s| HistorySource::new(s.path.clone(), s.format, s.label.clone()))
+            .collect()
+    } else {
+        detect_history_file()
+            .map(|path| vec![HistorySource::new(path, args.format, None)])
+            .unwrap_or_default()
+    };
+
+    // Last, so that on timestamp ties its richer entries are the newer ones.
+    if let Some(path) = store::existing_store() {
+        sources.push(HistorySource::new(
+            path,
+            Some(HistoryFormat::Ihistory),
+            Some("ihistory".to_string()),
+        ));
+    }
+
+    (!sources.is_empty()).then_some(sources)
+}
+
 pub fn run(args: Args) -> Result<i32, Box<dyn std::error::Error>> {
-    let history_path = args.file.or_else(detect_history_file).ok_or_else(|| {
+    let config = config::load()?;
+    let sources = history_sources(&args, &config).ok_or_else(|| {
         io::Error::new(
             io::ErrorKind::NotFound,
             "Could not find history file. Please specify one with --file",
         )
     })?;
 
-    let entries = load_history(&history_path, args.limit)?;
-    if entries.is_empty() {
-        return Err("No history entries found".into());
-    }
-
     // Use /dev/tty so TUI works inside $() subshells
     let mut tty = File::options().read(true).write(true).open("/dev/tty")?;
 
     enable_raw_mode()?;
-    execute!(tty, EnterAlternateScreen)?;
+    // Inline only while that leaves the prompt line in view; any taller is
+    // the whole screen anyway. A terminal that won't say where its cursor
+    // is gets the whole screen too.
+    let (_, terminal_rows) = terminal::size()?;
+    let inline_rows = args
+        .height
+        .or(config.height)
+        .map(|height| height.rows(terminal_rows).max(inline::MIN_ROWS))
+        .filter(|&rows| rows < terminal_rows);
+    let (viewport, prompt) = match inline_rows.map(|rows| inline::make_room(&mut tty, rows)) {
+        Some(Ok((area, prompt))) => (Viewport::Fixed(area), Some(prompt)),
+        _ => {
+            execute!(tty, EnterAlternateScreen)?;
+            (Viewport::Fullscreen, None)
+        }
+    };
+    execute!(tty, EnableBracketedPaste)?;
     let backend = CrosstermBackend::new(tty);
-    let mut terminal = Terminal::new(backend)?;
+    let mut terminal = Terminal::with_options(backend, TerminalOptions { viewport })?;
 
-    let mut app = App::new(entries, history_path, args.query);
-    let result = run_event_loop(&mut terminal, &mut app);
+    // The log mirrors the shell's own history, so tagging entries with it
+    // says nothing; only several real history files warrant tags.
+    let shell_sources = sources
+        .iter()
+        .filter(|s| s.format != Some(HistoryFormat::Ihistory))
+        .count();
+    let theme = Theme::from_config(
+        &config.theme,
+        args.theme,
+        ColorDepth::detect(),
+        theme::no_color(),
+    );
+    let ui = UI::new()
+        .show_sources(shell_sources > 1)
+        .theme(theme)
+        .layout(args.layout.unwrap_or(config.layout));
+    // The UI opens straight away and fills in as history arrives.
+    let loader = load_in_background(sources);
+    let mut search_engine = SearchEngine::new()
+        .ranking(config.ranking)
+        .case(args.case.unwrap_or(config.case))
+        .normalize(args.normalize || config.normalize);
+    search_engine.set_mode(args.mode.unwrap_or(config.mode));
+    let mut app = App::new(
+        History::new(args.limit),
+        Some(loader),
+        args.query,
+        search_engine,
+        ui,
+        config.join,
+        Keys::new(args.keymap.unwrap_or(config.keymap), &config),
+    );
+    let result = run_event_loop(&mut terminal, &mut app, prompt.is_some());
 
     // Cleanup terminal before any output
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), DisableBracketedPaste)?;
+    match prompt {
+        // Wipe the search and put the cursor back on the prompt line.
+        Some(prompt) => {
+            terminal.clear()?;
+            terminal.set_cursor_position(prompt)?;
+            terminal.show_cursor()?;
+        }
+        None => execute!(terminal.backend_mut(), LeaveAlternateScreen)?,
+    }
     disable_raw_mode()?;
     drop(terminal);
 
@@ -183,49 +581,224 @@ fn copy_to_clipboard(text: &str) {
     }
 }
 
-fn run_event_loop(
+fn draw(
     terminal: &mut Terminal<CrosstermBackend<File>>,
     app: &mut App,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let previewed = app.results.get(app.selected).map(|result| result.index);
+    if previewed != app.previewed {
+        app.previewed = previewed;
+        app.preview_scroll = 0;
+    }
     terminal.draw(|frame| {
         app.scroll_offset = app.ui.render(
             frame,
             &app.query,
+            app.search_engine.mode().label(),
+            app.history.entries(),
             &app.results,
+            &app.marked,
+            app.vi_mode,
+            app.keys.bindings(app.vi_mode),
+            app.search_engine.scope().label(),
+            app.loader.is_some(),
             app.selected,
             app.scroll_offset,
             &mut app.list_state,
+            &mut app.preview_scroll,
             app.status_message.as_deref(),
         );
     })?;
+    Ok(())
+}
+
+/// `inline` is set when drawing below the prompt, where the area has to be
+/// fitted to the terminal by hand after a resize.
+fn run_event_loop(
+    terminal: &mut Terminal<CrosstermBackend<File>>,
+    app: &mut App,
+    inline: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    draw(terminal, app)?;
 
     loop {
-        match event::read()? {
-            Event::Key(key) if key.kind == KeyEventKind::Press => {
-                app.handle_key(key.code, key.modifiers);
-            }
-            Event::Resize(_, _) => {
-                // Redraw on resize
+        // While history is still loading, wake up regularly to take it in.
+        let event_ready = app.loader.is_none() || event::poll(LOAD_POLL_INTERVAL)?;
+        let mut redraw = app.receive_history()?;
+
+        if event_ready {
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press => {
+                    app.handle_key(key.code, key.modifiers);
+                    redraw = true;
+                }
+                Event::Paste(text) => {
+                    app.paste(&text);
+                    redraw = true;
+                }
+                Event::Resize(width, height) => {
+                    if inline {
+                        let area = terminal.get_frame().area();
+                        terminal.resize(inline::resized(area, width, height))?;
+                    }
+                    redraw = true;
+                }
+                _ => {} // Skip other events without redrawing
             }
-            _ => continue, // Skip other events without redrawing
         }
 
         if app.should_quit {
             break;
         }
 
-        terminal.draw(|frame| {
-            app.scroll_offset = app.ui.render(
-                frame,
-                &app.query,
-                &app.results,
-                app.selected,
-                app.scroll_offset,
-                &mut app.list_state,
-                app.status_message.as_deref(),
-            );
-        })?;
+        if redraw {
+            draw(terminal, app)?;
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ihistory::history::HistoryEntry;
+
+    fn vi_app(commands: &[&str]) -> App {
+        app(commands, Keymap::Vi, UI::new())
+    }
+
+    fn app(commands: &[&str], keymap: Keymap, ui: UI) -> App {
+        let mut history = History::default();
+        history.extend(commands.iter().map(|command| HistoryEntry {
+            command: command.to_string(),
+            ..Default::default()
+        }));
+        App::new(
+            history,
+            None,
+            None,
+            SearchEngine::new(),
+            ui,
+            Join::Newline,
+            Keys::new(keymap, &Config::default()),
+        )
+    }
+
+    fn press(app: &mut App, keys: &str) {
+        for c in keys.chars() {
+            app.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn esc_switches_to_normal_mode_instead_of_quitting() {
+        let mut app = vi_app(&["ls", "pwd"]);
+        press(&mut app, "l");
+        app.handle_key(KeyCode::Esc, KeyModifiers::NONE);
+        assert!(!app.should_quit);
+        assert_eq!(app.vi_mode, Some(ViMode::Normal));
+
+        // Letters are commands now, not query text.
+        press(&mut app, "jk");
+        assert_eq!(app.query.text(), "l");
+
+        press(&mut app, "/s");
+        assert_eq!(app.vi_mode, Some(ViMode::Insert));
+        assert_eq!(app.query.text(), "ls");
+    }
+
+    #[test]
+    fn moves_through_results_in_normal_mode() {
+        let mut app = vi_app(&["a", "b", "c", "d"]);
+        app.handle_key(KeyCode::Esc, KeyModifiers::NONE);
+        press(&mut app, "jj");
+        assert_eq!(app.selected, 2);
+        press(&mut app, "G");
+        assert_eq!(app.selected, 3);
+        press(&mut app, "k");
+        assert_eq!(app.selected, 2);
+        // A lone `g` waits for the second one; anything else drops it.
+        press(&mut app, "gj");
+        assert_eq!(app.selected, 3);
+        press(&mut app, "gg");
+        assert_eq!(app.selected, 0);
+        app.handle_key(KeyCode::Char('d'), KeyModifiers::CONTROL);
+        assert_eq!(app.selected, 3);
+    }
+
+    #[test]
+    fn down_stops_at_the_last_result() {
+        let mut app = app(&["a", "b"], Keymap::Emacs, UI::new());
+        for _ in 0..3 {
+            app.handle_key(KeyCode::Down, KeyModifiers::NONE);
+            app.handle_key(KeyCode::Char('n'), KeyModifiers::CONTROL);
+        }
+        assert_eq!(app.selected, 1);
+        assert_eq!(app.query.text(), "");
+    }
+
+    #[test]
+    fn arrows_follow_the_screen_in_reverse() {
+        let ui = UI::new().layout(config::Layout::Reverse);
+        let mut app = app(&["a", "b", "c"], Keymap::Emacs, ui);
+        // The best match is at the bottom, so going up goes down the list.
+        app.handle_key(KeyCode::Up, KeyModifiers::NONE);
+        app.handle_key(KeyCode::Up, KeyModifiers::NONE);
+        assert_eq!(app.selected, 2);
+        app.handle_key(KeyCode::Down, KeyModifiers::NONE);
+        assert_eq!(app.selected, 1);
+    }
+
+    #[test]
+    fn vi_jumps_follow_the_screen_in_reverse() {
+        let ui = UI::new().layout(config::Layout::Reverse);
+        let mut app = app(&["a", "b", "c"], Keymap::Vi, ui);
+        app.handle_key(KeyCode::Esc, KeyModifiers::NONE);
+        // `G` goes to the bottom of the screen, where the best match is.
+        press(&mut app, "kG");
+        assert_eq!(app.selected, 0);
+        press(&mut app, "gg");
+        assert_eq!(app.selected, 2);
+    }
+
+    #[test]
+    fn keeps_the_selected_entry_while_history_loads() {
+        let mut app = app(&["make git", "vim gitconfig"], Keymap::Emacs, UI::new());
+        press(&mut app, "git");
+        app.handle_key(KeyCode::Down, KeyModifiers::NONE);
+        let selected = app.results[app.selected].index;
+
+        let (sender, loader) = std::sync::mpsc::channel();
+        app.loader = Some(loader);
+        let older = ["git", "git status"].map(|command| HistoryEntry {
+            command: command.to_string(),
+            ..Default::default()
+        });
+        sender.send(Ok(older.to_vec())).unwrap();
+        assert!(app.receive_history().unwrap());
+
+        assert_eq!(app.results.len(), 4);
+        assert_ne!(app.selected, 1);
+        assert_eq!(app.results[app.selected].index, selected);
+    }
+
+    #[test]
+    fn adds_loaded_history_to_the_end_without_a_query() {
+        let mut app = app(&["ls", "pwd"], Keymap::Emacs, UI::new());
+        app.handle_key(KeyCode::Down, KeyModifiers::NONE);
+
+        let (sender, loader) = std::sync::mpsc::channel();
+        app.loader = Some(loader);
+        let older = ["make", "ls"].map(|command| HistoryEntry {
+            command: command.to_string(),
+            ..Default::default()
+        });
+        sender.send(Ok(older.to_vec())).unwrap();
+        assert!(app.receive_history().unwrap());
+
+        let indices: Vec<usize> = app.results.iter().map(|result| result.index).collect();
+        assert_eq!(indices, [0, 1, 2]);
+        assert_eq!(app.selected, 1);
+    }
+}
@@ -0,0 +1,84 @@
+//! Parsed history files, kept between runs under the cache directory so
+//! that the next run only parses what was appended since.
+//!
+//! Each history file gets its own cache file, named after a hash of its
+//! path. A cache is only ever a shortcut: one that is missing, unreadable or
+//! written by another version is ignored and the file parsed in full.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::history::{HistoryEntry, HistoryFormat};
+
+/// Bumped whenever the layout below or the parsers' output changes.
+const VERSION: u32 = 2;
+
+/// How many bytes before the cached offset are hashed to tell a file that
+/// grew from one that was rewritten.
+pub const FINGERPRINT_BYTES: u64 = 4096;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParsedFile {
+    version: u32,
+    pub path: PathBuf,
+    pub format: HistoryFormat,
+    /// Size and modification time of the file when it was last read.
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    /// How much of the file `entries` cover. Always falls between two
+    /// records, so parsing can pick up from there.
+    pub offset: u64,
+    /// `fingerprint` of the bytes just before `offset`.
+    pub fingerprint: u64,
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl ParsedFile {
+    pub fn new(path: &Path, format: HistoryFormat) -> Self {
+        Self {
+            version: VERSION,
+            path: path.to_path_buf(),
+            format,
+            len: 0,
+            modified: None,
+            offset: 0,
+            fingerprint: fingerprint(&[]),
+            entries: Vec::new(),
+        }
+    }
+}
+
+/// FNV-1a; unlike `DefaultHasher`, stable across Rust releases.
+pub fn fingerprint(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Where the cache for the history file at `path` lives.
+pub fn cache_path(path: &Path) -> Option<PathBuf> {
+    let hash = fingerprint(path.as_os_str().as_encoded_bytes());
+    Some(config::cache_dir()?.join(format!("{:016x}.bin", hash)))
+}
+
+/// The cache at `cache`, if there is a usable one for the history file at
+/// `path`.
+pub fn load(cache: &Path, path: &Path) -> Option<ParsedFile> {
+    let bytes = fs::read(cache).ok()?;
+    let parsed: ParsedFile = bincode::deserialize(&bytes).ok()?;
+    (parsed.version == VERSION && parsed.path == path).then_some(parsed)
+}
+
+/// Writes to a temporary file first, so that a run reading the cache
+/// concurrently never sees half of it.
+pub fn save(cache: &Path, parsed: &ParsedFile) -> io::Result<()> {
+    let bytes = bincode::serialize(parsed).map_err(io::Error::other)?;
+    let partial = cache.with_extension(format!("{}.tmp", std::process::id()));
+    fs::write(&partial, bytes)?;
+    fs::rename(&partial, cache)
+}
@@ -0,0 +1,362 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::history::HistoryFormat;
+use crate::keys::{self, Action, KeySequence};
+use crate::search::{CaseMode, Ranking, SearchMode};
+use crate::theme::ThemeConfig;
+
+/// Settings read from `config.toml` in the config directory. Every field is
+/// optional; a missing file is the same as an empty one.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// History files searched together when no `--file` is given.
+    pub sources: Vec<SourceConfig>,
+    /// Search mode on startup; `--mode` overrides it.
+    pub mode: SearchMode,
+    /// Letter-case handling; `--case` overrides it.
+    pub case: CaseMode,
+    /// Fold accents and full-width forms when matching; `--normalize`
+    /// turns it on too.
+    pub normalize: bool,
+    /// Frecency weights blended into match scores.
+    pub ranking: Ranking,
+    /// How several marked commands are put together on selection.
+    pub join: Join,
+    /// Key style; `--keymap` overrides it.
+    pub keymap: Keymap,
+    /// Bindings added to or replacing the defaults, for the emacs keymap
+    /// and vi's insert mode.
+    #[serde(deserialize_with = "keys::deserialize_overrides")]
+    pub keys: Vec<(KeySequence, Action)>,
+    /// The same for vi's normal mode.
+    #[serde(deserialize_with = "keys::deserialize_overrides")]
+    pub normal_keys: Vec<(KeySequence, Action)>,
+    /// Colours.
+    pub theme: ThemeConfig,
+    /// Draw below the prompt, this tall, instead of on the whole screen;
+    /// `--height` overrides it.
+    pub height: Option<Height>,
+    /// Where the query, results and preview go; `--layout` overrides it.
+    pub layout: Layout,
+}
+
+/// How the screen is arranged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Query at the top, results below it, best first.
+    #[default]
+    Default,
+    /// Query at the bottom, with the best result just above it and the
+    /// rest going up, as in fzf's default.
+    Reverse,
+    /// Query at the bottom, results top to bottom above it.
+    InputBottom,
+    /// Preview beside the results rather than under them, on terminals wide
+    /// enough for both.
+    PreviewRight,
+}
+
+/// How many terminal rows the search takes when drawn inline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Height {
+    Rows(u16),
+    /// A share of the terminal's height, so that it suits any window.
+    Percent(u16),
+}
+
+impl Height {
+    pub fn rows(self, terminal_rows: u16) -> u16 {
+        match self {
+            Height::Rows(rows) => rows,
+            Height::Percent(percent) => (terminal_rows as u32 * percent as u32 / 100) as u16,
+        }
+    }
+}
+
+impl FromStr for Height {
+    type Err = String;
+
+    /// `20` rows or `40%` of the terminal.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "invalid height `{}`: expected rows like 20 or a share like 40%",
+                s
+            )
+        };
+        match s.strip_suffix('%') {
+            Some(percent) => match percent.parse() {
+                Ok(percent @ 1..=100) => Ok(Height::Percent(percent)),
+                _ => Err(invalid()),
+            },
+            None => match s.parse() {
+                Ok(rows @ 1..) => Ok(Height::Rows(rows)),
+                _ => Err(invalid()),
+            },
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Height {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Which style of key bindings drives the search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Keymap {
+    /// Modeless, readline-style keys.
+    #[default]
+    Emacs,
+    /// Typing in insert mode; `Esc` switches to normal mode to move
+    /// through results.
+    Vi,
+}
+
+/// What goes between marked commands when several are selected at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Join {
+    /// One per line, for the shell to run in turn.
+    #[default]
+    Newline,
+    /// `a && b`, stopping at the first failure.
+    And,
+}
+
+impl Join {
+    pub fn separator(self) -> &'static str {
+        match self {
+            Join::Newline => "\n",
+            Join::And => " && ",
+        }
+    }
+}
+
+/// A `[[sources]]` entry.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourceConfig {
+    pub path: PathBuf,
+    /// Overrides format detection for this file.
+    pub format: Option<HistoryFormat>,
+    /// Tag shown next to entries from this file; defaults to the file name.
+    pub label: Option<String>,
+}
+
+/// `~/.config/ihistory` (or the platform equivalent), created on first use.
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = dirs::config_dir()?.join("ihistory");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir)
+}
+
+/// `~/.cache/ihistory` (or the platform equivalent), created on first use.
+/// Nothing in it is needed; it only saves work.
+pub fn cache_dir() -> Option<PathBuf> {
+    let dir = dirs::cache_dir()?.join("ihistory");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir)
+}
+
+/// Expands a leading `~/` so config paths can be written the way they are
+/// typed at a prompt.
+pub fn expand_tilde(path: &Path) -> PathBuf {
+    match path.strip_prefix("~") {
+        Ok(rest) => dirs::home_dir()
+            .map(|home| home.join(rest))
+            .unwrap_or_else(|| path.to_path_buf()),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+pub fn load() -> io::Result<Config> {
+    let Some(path) = config_dir().map(|dir| dir.join("config.toml")) else {
+        return Ok(Config::default());
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e),
+    };
+    parse(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+fn parse(text: &str) -> Result<Config, toml::de::Error> {
+    let mut config: Config = toml::from_str(text)?;
+    for source in &mut config.sources {
+        source.path = expand_tilde(&source.path);
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_config_is_default() {
+        let config = parse("").unwrap();
+        assert!(config.sources.is_empty());
+    }
+
+    #[test]
+    fn parses_sources() {
+        let config = parse(
+            r#"
+            [[sources]]
+            path = "/home/me/.zsh_history"
+
+            [[sources]]
+            path = "/backups/server.txt"
+            format = "bash"
+            label = "server"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.sources.len(), 2);
+        assert_eq!(config.sources[0].format, None);
+        assert_eq!(config.sources[1].format, Some(HistoryFormat::Bash));
+        assert_eq!(config.sources[1].label.as_deref(), Some("server"));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert!(parse("colour = \"red\"").is_err());
+        assert!(parse("[[sources]]\npath = \"a\"\nformt = \"zsh\"").is_err());
+    }
+
+    #[test]
+    fn parses_mode() {
+        let config = parse("mode = \"word-boundary\"").unwrap();
+        assert_eq!(config.mode, SearchMode::WordBoundary);
+        assert!(parse("mode = \"wordish\"").is_err());
+    }
+
+    #[test]
+    fn parses_case() {
+        assert_eq!(parse("case = \"smart\"").unwrap().case, CaseMode::Smart);
+        assert_eq!(parse("").unwrap().case, CaseMode::Insensitive);
+    }
+
+    #[test]
+    fn parses_normalize() {
+        assert!(parse("normalize = true").unwrap().normalize);
+        assert!(!parse("").unwrap().normalize);
+    }
+
+    #[test]
+    fn parses_join() {
+        assert_eq!(parse("join = \"and\"").unwrap().join, Join::And);
+        assert_eq!(parse("").unwrap().join, Join::Newline);
+    }
+
+    #[test]
+    fn parses_keymap() {
+        assert_eq!(parse("keymap = \"vi\"").unwrap().keymap, Keymap::Vi);
+        assert_eq!(parse("").unwrap().keymap, Keymap::Emacs);
+        assert!(parse("keymap = \"vim\"").is_err());
+    }
+
+    #[test]
+    fn parses_keys() {
+        let config = parse(
+            r#"
+            [keys]
+            ctrl-j = "down"
+            ctrl-d = "none"
+
+            [normal_keys]
+            "g t" = "cycle-mode"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keys.len(), 2);
+        assert!(config
+            .keys
+            .contains(&("ctrl-j".parse().unwrap(), Action::Down)));
+        assert_eq!(
+            config.normal_keys,
+            [("g t".parse().unwrap(), Action::CycleMode)]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_actions() {
+        let err = parse(
+            "[keys]
+ctrl-foo = \"down\"",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("unknown key `foo`"), "{}", err);
+        assert!(parse(
+            "[keys]
+ctrl-j = \"downward\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_theme() {
+        use crate::theme::Preset;
+        use ratatui::style::Color;
+
+        let config = parse("[theme]\npreset = \"high-contrast\"\naccent = \"#ff8700\"").unwrap();
+        assert_eq!(config.theme.preset, Preset::HighContrast);
+        assert_eq!(config.theme.accent, Some(Color::Rgb(255, 135, 0)));
+        assert_eq!(config.theme.matched, None);
+        assert!(parse("[theme]\naccent = \"reddish\"").is_err());
+        assert!(parse("[theme]\npreset = \"sepia\"").is_err());
+    }
+
+    #[test]
+    fn parses_height() {
+        assert_eq!(
+            parse("height = \"40%\"").unwrap().height,
+            Some(Height::Percent(40))
+        );
+        assert_eq!(
+            parse("height = \"15\"").unwrap().height,
+            Some(Height::Rows(15))
+        );
+        assert_eq!(parse("").unwrap().height, None);
+        for bad in ["0", "0%", "120%", "tall", "-3"] {
+            assert!(bad.parse::<Height>().is_err(), "{}", bad);
+        }
+        assert_eq!(Height::Percent(40).rows(50), 20);
+        assert_eq!(Height::Rows(15).rows(50), 15);
+    }
+
+    #[test]
+    fn parses_layout() {
+        assert_eq!(
+            parse("layout = \"preview-right\"").unwrap().layout,
+            Layout::PreviewRight
+        );
+        assert_eq!(parse("").unwrap().layout, Layout::Default);
+        assert!(parse("layout = \"sideways\"").is_err());
+    }
+
+    #[test]
+    fn parses_partial_ranking() {
+        let config = parse("[ranking]\nfrequency = 0").unwrap();
+        assert_eq!(config.ranking.frequency, 0.0);
+        assert_eq!(config.ranking.recency, Ranking::default().recency);
+    }
+}
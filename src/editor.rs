@@ -0,0 +1,233 @@
+//! The query line: text plus a cursor, with readline-style editing.
+//!
+//! The cursor is a byte offset that always sits on a char boundary. Every
+//! editing method returns whether the text changed, so callers know when
+//! to search again.
+
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Debug, Clone, Default)]
+pub struct LineEditor {
+    text: String,
+    cursor: usize,
+}
+
+impl LineEditor {
+    /// Starts with the cursor at the end of `text`.
+    pub fn new(text: String) -> Self {
+        let cursor = text.len();
+        Self { text, cursor }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Columns taken by the text before the cursor, where the terminal
+    /// cursor goes.
+    pub fn cursor_width(&self) -> usize {
+        self.text[..self.cursor].width()
+    }
+
+    pub fn before_cursor(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    pub fn insert(&mut self, c: char) -> bool {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        true
+    }
+
+    /// Inserts pasted text. Line breaks and tabs become spaces: the query
+    /// is one line, and a space is what separates terms.
+    pub fn insert_str(&mut self, text: &str) -> bool {
+        let text: String = text
+            .chars()
+            .map(|c| if c.is_whitespace() { ' ' } else { c })
+            .filter(|c| !c.is_control())
+            .collect();
+        self.text.insert_str(self.cursor, &text);
+        self.cursor += text.len();
+        !text.is_empty()
+    }
+
+    pub fn backspace(&mut self) -> bool {
+        let start = self.prev_boundary();
+        self.delete_range(start, self.cursor)
+    }
+
+    pub fn delete(&mut self) -> bool {
+        let end = self.next_boundary();
+        self.delete_range(self.cursor, end)
+    }
+
+    /// Ctrl+W: the whitespace-separated word before the cursor, and the
+    /// whitespace after it.
+    pub fn delete_word_before(&mut self) -> bool {
+        let before = self.before_cursor().trim_end();
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| {
+            i + before[i..].chars().next().map_or(1, char::len_utf8)
+        });
+        self.delete_range(start, self.cursor)
+    }
+
+    /// Ctrl+U: everything before the cursor.
+    pub fn delete_to_start(&mut self) -> bool {
+        self.delete_range(0, self.cursor)
+    }
+
+    pub fn clear(&mut self) -> bool {
+        self.delete_range(0, self.text.len())
+    }
+
+    pub fn move_left(&mut self) {
+        self.cursor = self.prev_boundary();
+    }
+
+    pub fn move_right(&mut self) {
+        self.cursor = self.next_boundary();
+    }
+
+    pub fn move_home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Alt+B: to the start of the word before the cursor, where words are
+    /// runs of letters and digits as in readline.
+    pub fn move_word_left(&mut self) {
+        let before = self.before_cursor();
+        let word_end = before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_alphanumeric())
+            .map_or(0, |(i, _)| i);
+        self.cursor = before[..word_end]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !c.is_alphanumeric())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+    }
+
+    /// Alt+F: to the end of the word after the cursor.
+    pub fn move_word_right(&mut self) {
+        let after = &self.text[self.cursor..];
+        let word_start = after
+            .char_indices()
+            .find(|(_, c)| c.is_alphanumeric())
+            .map_or(after.len(), |(i, _)| i);
+        let word_end = after[word_start..]
+            .char_indices()
+            .find(|(_, c)| !c.is_alphanumeric())
+            .map_or(after.len(), |(i, _)| word_start + i);
+        self.cursor += word_end;
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.before_cursor()
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .chars()
+            .next()
+            .map_or(self.cursor, |c| self.cursor + c.len_utf8())
+    }
+
+    fn delete_range(&mut self, start: usize, end: usize) -> bool {
+        if start == end {
+            return false;
+        }
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(text: &str, cursor: usize) -> LineEditor {
+        LineEditor {
+            text: text.to_string(),
+            cursor,
+        }
+    }
+
+    #[test]
+    fn inserts_and_deletes_at_the_cursor() {
+        let mut editor = at("gt", 1);
+        editor.insert('i');
+        assert_eq!((editor.text(), editor.cursor), ("git", 2));
+        assert!(editor.delete());
+        assert_eq!(editor.text(), "gi");
+        assert!(!editor.delete());
+        assert!(editor.backspace());
+        assert_eq!((editor.text(), editor.cursor), ("g", 1));
+    }
+
+    #[test]
+    fn steps_over_whole_chars() {
+        let mut editor = LineEditor::new("日é".to_string());
+        editor.move_left();
+        assert_eq!(editor.before_cursor(), "日");
+        assert_eq!(editor.cursor_width(), 2);
+        editor.backspace();
+        assert_eq!(editor.text(), "é");
+        editor.move_right();
+        assert_eq!(editor.cursor, "é".len());
+    }
+
+    #[test]
+    fn deletes_the_word_before_the_cursor() {
+        let mut editor = LineEditor::new("git push  ".to_string());
+        assert!(editor.delete_word_before());
+        assert_eq!(editor.text(), "git ");
+        assert!(editor.delete_word_before());
+        assert_eq!(editor.text(), "");
+        assert!(!editor.delete_word_before());
+
+        let mut editor = at("ls   -la 日本", 9);
+        editor.delete_word_before();
+        assert_eq!((editor.text(), editor.cursor), ("ls   日本", 5));
+    }
+
+    #[test]
+    fn moves_by_words() {
+        let mut editor = LineEditor::new("kubectl get-pods -n".to_string());
+        editor.move_word_left();
+        assert_eq!(editor.before_cursor(), "kubectl get-pods -");
+        editor.move_word_left();
+        assert_eq!(editor.before_cursor(), "kubectl get-");
+        editor.move_word_left();
+        editor.move_word_left();
+        assert_eq!(editor.cursor, 0);
+        editor.move_word_right();
+        assert_eq!(editor.before_cursor(), "kubectl");
+        editor.move_word_right();
+        assert_eq!(editor.before_cursor(), "kubectl get");
+    }
+
+    #[test]
+    fn pastes_onto_one_line() {
+        let mut editor = at("echo ", 5);
+        assert!(editor.insert_str("a\nb\tc\u{7}"));
+        assert_eq!(editor.text(), "echo a b c");
+        assert_eq!(editor.cursor, editor.text().len());
+    }
+
+    #[test]
+    fn deletes_to_the_start() {
+        let mut editor = at("git push", 4);
+        assert!(editor.delete_to_start());
+        assert_eq!((editor.text(), editor.cursor), ("push", 0));
+    }
+}
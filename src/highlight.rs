@@ -0,0 +1,351 @@
+//! Shell syntax, roughly: enough to colour a command in the preview.
+//!
+//! This is not a parser. It splits a command into words and operators the
+//! way a POSIX shell would, then guesses each word's role from where it
+//! stands. Anything it does not understand comes out as plain text, so a
+//! command in another shell's syntax only loses colour.
+
+use std::ops::Range;
+
+/// The role of a piece of a command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    /// The program being run, or a keyword like `if`.
+    Command,
+    /// An option: `-v`, `--force`.
+    Flag,
+    /// Anything quoted.
+    String,
+    /// `$HOME`, `${x}`, `$(…)`, and the name in `FOO=bar`.
+    Variable,
+    /// Pipes, `&&`, `;`, redirections and brackets.
+    Operator,
+    Comment,
+    /// Arguments, file names and whitespace.
+    Plain,
+}
+
+/// Words after which the next word is a command again.
+const PREFIXES: &[&str] = &[
+    "sudo", "doas", "exec", "command", "builtin", "nohup", "time", "if", "then", "elif", "else",
+    "while", "until", "do", "!",
+];
+
+/// The pieces of `command`, in order and covering all of it, as byte ranges
+/// with neighbours of the same role merged.
+pub fn highlight(command: &str) -> Vec<(Token, Range<usize>)> {
+    let mut lexer = Lexer {
+        text: command,
+        bytes: command.as_bytes(),
+        spans: Vec::new(),
+    };
+    lexer.run();
+    lexer.spans
+}
+
+fn is_operator(byte: u8) -> bool {
+    matches!(byte, b'|' | b'&' | b';' | b'<' | b'>' | b'(' | b')')
+}
+
+fn is_name(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+struct Lexer<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    spans: Vec<(Token, Range<usize>)>,
+}
+
+impl Lexer<'_> {
+    fn push(&mut self, token: Token, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        match self.spans.last_mut() {
+            Some((last, previous)) if *last == token && previous.end == range.start => {
+                previous.end = range.end;
+            }
+            _ => self.spans.push((token, range)),
+        }
+    }
+
+    /// The end of the character starting at `i`.
+    fn char_end(&self, i: usize) -> usize {
+        i + self.text[i..].chars().next().map_or(1, char::len_utf8)
+    }
+
+    fn run(&mut self) {
+        let mut i = 0;
+        let mut command_position = true;
+        while i < self.bytes.len() {
+            match self.bytes[i] {
+                b'\n' => {
+                    self.push(Token::Plain, i..i + 1);
+                    command_position = true;
+                    i += 1;
+                }
+                b' ' | b'\t' => {
+                    self.push(Token::Plain, i..i + 1);
+                    i += 1;
+                }
+                b'#' => {
+                    let end = self.text[i..]
+                        .find('\n')
+                        .map_or(self.bytes.len(), |n| i + n);
+                    self.push(Token::Comment, i..end);
+                    i = end;
+                }
+                byte if is_operator(byte) => {
+                    let start = i;
+                    while i < self.bytes.len() && is_operator(self.bytes[i]) {
+                        i += 1;
+                    }
+                    let operator = &self.text[start..i];
+                    self.push(Token::Operator, start..i);
+                    // After a redirection comes a file name, and after `)`
+                    // whatever follows a command.
+                    command_position =
+                        !operator.contains(['<', '>']) && operator.ends_with(['|', '&', ';', '(']);
+                }
+                _ => {
+                    let start = i;
+                    i = self.word(i, command_position);
+                    let word = &self.text[start..i];
+                    command_position = PREFIXES.contains(&word) || is_assignment(word);
+                }
+            }
+        }
+    }
+
+    /// Lexes the word starting at `start` and returns where it ends.
+    fn word(&mut self, start: usize, command_position: bool) -> usize {
+        let mut i = start;
+        let bare = if self.bytes[i] == b'-' {
+            Token::Flag
+        } else if command_position {
+            if let Some(name_end) = assignment_name_end(&self.text[start..]) {
+                self.push(Token::Variable, start..start + name_end);
+                i += name_end;
+                Token::Plain
+            } else {
+                Token::Command
+            }
+        } else {
+            Token::Plain
+        };
+
+        while i < self.bytes.len() {
+            match self.bytes[i] {
+                b' ' | b'\t' | b'\n' => break,
+                byte if is_operator(byte) => break,
+                b'\\' => {
+                    let end = if i + 1 < self.bytes.len() {
+                        self.char_end(i + 1)
+                    } else {
+                        i + 1
+                    };
+                    self.push(bare, i..end);
+                    i = end;
+                }
+                b'\'' => {
+                    let end = self.text[i + 1..]
+                        .find('\'')
+                        .map_or(self.bytes.len(), |n| i + 1 + n + 1);
+                    self.push(Token::String, i..end);
+                    i = end;
+                }
+                b'"' => i = self.double_quoted(i),
+                b'$' => i = self.dollar(i, bare),
+                _ => {
+                    let end = self.char_end(i);
+                    self.push(bare, i..end);
+                    i = end;
+                }
+            }
+        }
+        i
+    }
+
+    /// A `"…"` string from `start`, with any variables in it picked out.
+    fn double_quoted(&mut self, start: usize) -> usize {
+        self.push(Token::String, start..start + 1);
+        let mut i = start + 1;
+        while i < self.bytes.len() {
+            match self.bytes[i] {
+                b'"' => {
+                    self.push(Token::String, i..i + 1);
+                    return i + 1;
+                }
+                b'\\' if i + 1 < self.bytes.len() => {
+                    let end = self.char_end(i + 1);
+                    self.push(Token::String, i..end);
+                    i = end;
+                }
+                b'$' => i = self.dollar(i, Token::String),
+                _ => {
+                    let end = self.char_end(i);
+                    self.push(Token::String, i..end);
+                    i = end;
+                }
+            }
+        }
+        i
+    }
+
+    /// An expansion starting with the `$` at `start`. A lone `$` is just
+    /// part of the word around it.
+    fn dollar(&mut self, start: usize, around: Token) -> usize {
+        let next = self.bytes.get(start + 1).copied();
+        let end = match next {
+            Some(open @ (b'{' | b'(')) => {
+                let close = if open == b'{' { b'}' } else { b')' };
+                let mut depth = 0;
+                let mut end = self.bytes.len();
+                for (offset, &byte) in self.bytes[start + 1..].iter().enumerate() {
+                    if byte == open {
+                        depth += 1;
+                    } else if byte == close {
+                        depth -= 1;
+                        if depth == 0 {
+                            end = start + 1 + offset + 1;
+                            break;
+                        }
+                    }
+                }
+                end
+            }
+            Some(byte) if is_name(byte) && !byte.is_ascii_digit() => {
+                let length = self.bytes[start + 1..]
+                    .iter()
+                    .take_while(|&&byte| is_name(byte))
+                    .count();
+                start + 1 + length
+            }
+            Some(b'?' | b'#' | b'@' | b'*' | b'!' | b'$' | b'-' | b'0'..=b'9') => start + 2,
+            _ => {
+                self.push(around, start..start + 1);
+                return start + 1;
+            }
+        };
+        self.push(Token::Variable, start..end);
+        end
+    }
+}
+
+/// The length of `NAME=` at the start of `word`, if it is an assignment.
+fn assignment_name_end(word: &str) -> Option<usize> {
+    let bytes = word.as_bytes();
+    if !bytes
+        .first()
+        .is_some_and(|&byte| is_name(byte) && !byte.is_ascii_digit())
+    {
+        return None;
+    }
+    let length = bytes.iter().take_while(|&&byte| is_name(byte)).count();
+    (bytes.get(length) == Some(&b'=')).then_some(length + 1)
+}
+
+fn is_assignment(word: &str) -> bool {
+    assignment_name_end(word).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Each piece of `command` with its role, trimmed, leaving out bare
+    /// whitespace.
+    fn pieces(command: &str) -> Vec<(Token, &str)> {
+        highlight(command)
+            .into_iter()
+            .map(|(token, range)| (token, command[range].trim()))
+            .filter(|(_, text)| !text.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn covers_the_whole_command() {
+        let command = "echo \"$HOME\" | grep -i 'x y' # done";
+        let spans = highlight(command);
+        let mut end = 0;
+        for (_, range) in &spans {
+            assert_eq!(range.start, end);
+            end = range.end;
+        }
+        assert_eq!(end, command.len());
+    }
+
+    #[test]
+    fn tells_commands_from_arguments() {
+        use Token::*;
+        assert_eq!(
+            pieces("git log --oneline -n 5 | head && ls;cd /tmp"),
+            [
+                (Command, "git"),
+                (Plain, "log"),
+                (Flag, "--oneline"),
+                (Flag, "-n"),
+                (Plain, "5"),
+                (Operator, "|"),
+                (Command, "head"),
+                (Operator, "&&"),
+                (Command, "ls"),
+                (Operator, ";"),
+                (Command, "cd"),
+                (Plain, "/tmp"),
+            ]
+        );
+    }
+
+    #[test]
+    fn picks_out_strings_and_variables() {
+        use Token::*;
+        assert_eq!(
+            pieces(r#"FOO=1 echo "hi $USER" 'no $x' ${PATH}:$(pwd) $?"#),
+            [
+                (Variable, "FOO="),
+                (Plain, "1"),
+                (Command, "echo"),
+                (String, "\"hi"),
+                (Variable, "$USER"),
+                (String, "\""),
+                (String, "'no $x'"),
+                (Variable, "${PATH}"),
+                (Plain, ":"),
+                (Variable, "$(pwd)"),
+                (Variable, "$?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_redirections_and_prefixes() {
+        use Token::*;
+        assert_eq!(
+            pieces("sudo make install > log 2>&1\nls # all"),
+            [
+                (Command, "sudo"),
+                (Command, "make"),
+                (Plain, "install"),
+                (Operator, ">"),
+                (Plain, "log 2"),
+                (Operator, ">&"),
+                (Plain, "1"),
+                (Command, "ls"),
+                (Comment, "# all"),
+            ]
+        );
+    }
+
+    #[test]
+    fn survives_unterminated_quotes_and_non_ascii() {
+        use Token::*;
+        assert_eq!(
+            pieces("echo 'héllo"),
+            [(Command, "echo"), (String, "'héllo")]
+        );
+        assert_eq!(pieces("echo \"a\\"), [(Command, "echo"), (String, "\"a\\")]);
+        assert_eq!(pieces("café $"), [(Command, "café"), (Plain, "$")]);
+    }
+}
@@ -1,20 +1,186 @@
-use std::collections::{HashMap, HashSet};
-use std::fs::{self, File, OpenOptions};
-use std::io::{BufRead, BufReader, Write};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
 
-#[derive(Debug, Clone)]
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::cache;
+use crate::config;
+use crate::store;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct HistoryEntry {
     pub command: String,
+    /// When the command was last run.
+    pub timestamp: Option<i64>,
+    #[allow(dead_code)]
+    pub raw_line: Option<String>,
+    /// Every run of the command across the loaded history, newest first.
+    pub occurrences: Vec<Occurrence>,
+    /// Label of the history file the entry was read from.
+    #[serde(skip)]
+    pub source: Option<Arc<str>>,
+    /// Where and how the command ran. Only ihistory's own log records
+    /// these; entries from shell history files leave them empty.
+    pub cwd: Option<PathBuf>,
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<u64>,
+    pub hostname: Option<String>,
+    pub session: Option<String>,
+    /// Whether the entry comes from ihistory's own log rather than a
+    /// shell's history file.
+    pub logged: bool,
+}
+
+/// One run of a command, as it appeared in a history file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Occurrence {
     pub timestamp: Option<i64>,
     #[allow(dead_code)]
     pub raw_line: Option<String>,
+    /// Label of the history file the run was read from.
+    #[serde(skip)]
+    pub source: Option<Arc<str>>,
+    /// Where the run happened, when ihistory's log recorded it.
+    #[serde(skip)]
+    pub cwd: Option<PathBuf>,
+    #[serde(skip)]
+    pub logged: bool,
+}
+
+/// How far apart the shell's history file and ihistory's log may stamp
+/// the same run: one takes the time the command started, the other when
+/// the shell got round to saving it.
+const SAME_RUN_SECONDS: i64 = 5;
+
+impl Occurrence {
+    /// Takes in what `copy`, this run as the other kind of file recorded
+    /// it, knows and this does not.
+    fn merge(&mut self, copy: Occurrence) {
+        self.timestamp = self.timestamp.or(copy.timestamp);
+        self.cwd = self.cwd.take().or(copy.cwd);
+    }
+}
+
+/// A command's runs that the other kind of file may turn out to have
+/// recorded too, as indices into its entry's occurrences. Only a shell's
+/// history file and ihistory's log see the same runs; two shell files are
+/// two machines or two shells.
+#[derive(Debug, Default)]
+struct Unpaired {
+    /// Runs from the log, by timestamp.
+    logged: BTreeMap<i64, Vec<usize>>,
+    /// Runs from shell files, by timestamp.
+    shell: BTreeMap<i64, Vec<usize>>,
+    /// Runs from shell files that saved no timestamp. Any logged run may
+    /// be one of them.
+    untimed: Vec<usize>,
+}
+
+impl Unpaired {
+    fn add(&mut self, occurrence: &Occurrence, index: usize) {
+        match (occurrence.logged, occurrence.timestamp) {
+            (true, Some(timestamp)) => self.logged.entry(timestamp).or_default().push(index),
+            (false, Some(timestamp)) => self.shell.entry(timestamp).or_default().push(index),
+            (false, None) => self.untimed.push(index),
+            // The log stamps every run.
+            (true, None) => {}
+        }
+    }
+
+    /// Takes the run that `occurrence` is a copy of, if one is unpaired.
+    fn take_match(&mut self, occurrence: &Occurrence) -> Option<usize> {
+        match (occurrence.logged, occurrence.timestamp) {
+            (true, Some(timestamp)) => {
+                take_nearest(&mut self.shell, timestamp).or_else(|| self.untimed.pop())
+            }
+            (true, None) => self.untimed.pop(),
+            (false, Some(timestamp)) => take_nearest(&mut self.logged, timestamp),
+            (false, None) => {
+                let mut oldest = self.logged.first_entry()?;
+                let index = oldest.get_mut().pop();
+                if oldest.get().is_empty() {
+                    oldest.remove();
+                }
+                index
+            }
+        }
+    }
+}
+
+/// Takes the run within `SAME_RUN_SECONDS` of `timestamp` closest to it.
+fn take_nearest(runs: &mut BTreeMap<i64, Vec<usize>>, timestamp: i64) -> Option<usize> {
+    let window =
+        timestamp.saturating_sub(SAME_RUN_SECONDS)..=timestamp.saturating_add(SAME_RUN_SECONDS);
+    let key = runs
+        .range(window)
+        .map(|(&key, _)| key)
+        .min_by_key(|&key| key.abs_diff(timestamp))?;
+    let indices = runs.get_mut(&key)?;
+    let index = indices.pop();
+    if indices.is_empty() {
+        runs.remove(&key);
+    }
+    index
+}
+
+impl HistoryEntry {
+    pub fn run_count(&self) -> usize {
+        self.occurrences.len().max(1)
+    }
+
+    /// When the command was first run, if any run was timestamped.
+    pub fn first_seen(&self) -> Option<i64> {
+        self.occurrences
+            .iter()
+            .filter_map(|o| o.timestamp)
+            .chain(self.timestamp)
+            .min()
+    }
+
+    /// Takes in the metadata of an older run of the same command. The
+    /// shell's copy of a run ihistory's log also recorded may sort as the
+    /// newer one; the log's metadata should survive either way.
+    fn absorb(&mut self, older: HistoryEntry) {
+        if self.cwd.is_none() && older.cwd.is_some() {
+            self.cwd = older.cwd;
+            self.exit_code = older.exit_code;
+            self.duration_ms = older.duration_ms;
+            self.hostname = older.hostname;
+            self.session = older.session;
+        }
+    }
+}
+
+/// A history file to load, with an optional format override.
+#[derive(Debug, Clone)]
+pub struct HistorySource {
+    pub path: PathBuf,
+    pub format: Option<HistoryFormat>,
+    pub label: Arc<str>,
+}
+
+impl HistorySource {
+    pub fn new(path: PathBuf, format: Option<HistoryFormat>, label: Option<String>) -> Self {
+        let label = label.unwrap_or_else(|| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.display().to_string())
+        });
+        Self {
+            path,
+            format,
+            label: label.into(),
+        }
+    }
 }
 
 fn blocklist_path() -> Option<PathBuf> {
-    let config_dir = dirs::config_dir()?.join("ihistory");
-    fs::create_dir_all(&config_dir).ok()?;
-    Some(config_dir.join("deleted"))
+    Some(config::config_dir()?.join("deleted"))
 }
 
 fn load_blocklist() -> HashSet<String> {
@@ -44,6 +210,17 @@ fn add_to_blocklist(command: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+fn fish_history_path(home: &Path) -> PathBuf {
+    // fish ignores the platform data dir and follows XDG everywhere,
+    // including macOS.
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| home.join(".local/share"))
+        .join("fish")
+        .join("fish_history")
+}
+
 pub fn detect_history_file() -> Option<PathBuf> {
     let home = dirs::home_dir()?;
 
@@ -58,6 +235,11 @@ pub fn detect_history_file() -> Option<PathBuf> {
             if bash_history.exists() {
                 return Some(bash_history);
             }
+        } else if shell.contains("fish") {
+            let fish_history = fish_history_path(&home);
+            if fish_history.exists() {
+                return Some(fish_history);
+            }
         }
     }
 
@@ -71,11 +253,12 @@ pub fn detect_history_file() -> Option<PathBuf> {
         return Some(bash_history);
     }
 
-    None
-}
+    let fish_history = fish_history_path(&home);
+    if fish_history.exists() {
+        return Some(fish_history);
+    }
 
-fn is_zsh_format(path: &Path) -> bool {
-    path.to_string_lossy().contains("zsh")
+    None
 }
 
 /// Zsh "metafies" bytes that collide with its internal markers (0x83–0x9D
@@ -157,100 +340,775 @@ fn parse_bash_line(line: &str) -> Option<String> {
     }
 }
 
-pub fn load_history(path: &Path, limit: usize) -> Result<Vec<HistoryEntry>, std::io::Error> {
-    let content = fs::read(path)?;
-    let is_zsh = is_zsh_format(path);
+/// Fish stores history as a YAML-like list of records:
+///
+/// ```text
+/// - cmd: git status
+///   when: 1706500000
+///   paths:
+///     - src/
+/// ```
+///
+/// It is not real YAML: `cmd` is written unquoted, with backslashes doubled
+/// and newlines escaped as `\n`.
+fn unescape_fish(cmd: &str) -> String {
+    let mut out = String::with_capacity(cmd.len());
+    let mut chars = cmd.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            // Anything else is not an escape fish produces; keep it verbatim.
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
 
-    let mut entry_data: HashMap<String, (Option<i64>, Option<String>)> = HashMap::new();
-    let mut order: Vec<String> = Vec::new();
-    let mut multiline_buffer: Option<(String, Option<i64>, String)> = None;
+fn parse_zsh(content: &[u8]) -> Vec<HistoryEntry> {
+    let mut entries = Vec::new();
+    let mut multiline_buffer: Option<ParsedZshLine> = None;
 
     for line_bytes in content.split(|&b| b == b'\n') {
-        let line = if is_zsh {
-            String::from_utf8_lossy(&unmetafy(line_bytes)).into_owned()
-        } else {
-            String::from_utf8_lossy(line_bytes).into_owned()
+        let line = String::from_utf8_lossy(&unmetafy(line_bytes)).into_owned();
+
+        if let Some(ref mut buffered) = multiline_buffer {
+            buffered.raw_line.push('\n');
+            buffered.raw_line.push_str(&line);
+            buffered.command.push('\n');
+            buffered.command.push_str(&line);
+
+            if !line.ends_with('\\') {
+                let parsed = multiline_buffer.take().unwrap();
+                entries.push(HistoryEntry {
+                    command: parsed.command,
+                    timestamp: parsed.timestamp,
+                    raw_line: Some(parsed.raw_line),
+                    ..Default::default()
+                });
+            }
+            continue;
+        }
+
+        if let Some(parsed) = parse_zsh_line(&line) {
+            if parsed.command.ends_with('\\') {
+                multiline_buffer = Some(parsed);
+            } else {
+                entries.push(HistoryEntry {
+                    command: parsed.command,
+                    timestamp: parsed.timestamp,
+                    raw_line: Some(parsed.raw_line),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    entries
+}
+
+/// With `HISTTIMEFORMAT` set, bash writes a `#EPOCH` comment line before
+/// each command.
+fn parse_bash_timestamp(line: &str) -> Option<i64> {
+    let digits = line.trim().strip_prefix('#')?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// The most bytes a timestamp marker and the lines after it may take up to
+/// be read back as one command.
+const LONGEST_JOINED_COMMAND: usize = 4096;
+
+fn parse_bash(content: &[u8]) -> Vec<HistoryEntry> {
+    // Each line with its byte offset and timestamp marker, if it is one.
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line_bytes in content.split(|&b| b == b'\n') {
+        let line = String::from_utf8_lossy(line_bytes);
+        lines.push((offset, parse_bash_timestamp(&line), line));
+        offset += line_bytes.len() + 1;
+    }
+
+    let mut entries: Vec<HistoryEntry> = Vec::new();
+    let mut push = |raw_line: &str, timestamp: Option<i64>| {
+        if let Some(command) = parse_bash_line(raw_line) {
+            entries.push(HistoryEntry {
+                command,
+                timestamp,
+                raw_line: Some(raw_line.to_string()),
+                ..Default::default()
+            });
+        }
+    };
+
+    // With `lithist`, bash saves a multi-line command as it was typed and
+    // reads everything up to the next marker back as one. Lines after a
+    // marker can also be commands saved with `HISTTIMEFORMAT` unset, one
+    // per line, so they are only joined when the next marker follows
+    // within a command's length. Bash writes a command and its newline in
+    // one go, so at the end of the file a final newline closes the last
+    // one; without it, the lines show separately until it is whole.
+    let mut i = 0;
+    while i < lines.len() {
+        let (start, timestamp, ref line) = lines[i];
+        i += 1;
+        let Some(timestamp) = timestamp else {
+            push(line, None);
+            continue;
         };
-        if is_zsh {
-            if let Some((ref mut cmd, ref ts, ref mut raw)) = multiline_buffer {
-                raw.push('\n');
-                raw.push_str(&line);
-                cmd.push('\n');
-                cmd.push_str(&line);
-
-                if !line.ends_with('\\') {
-                    let command = cmd.clone();
-                    let timestamp = *ts;
-                    let raw_line = raw.clone();
-                    multiline_buffer = None;
-
-                    if entry_data.contains_key(&command) {
-                        order.retain(|c| c != &command);
-                    }
-                    order.push(command.clone());
-                    entry_data.insert(command, (timestamp, Some(raw_line)));
+
+        let end = lines[i..]
+            .iter()
+            .position(|(_, marker, _)| marker.is_some())
+            .map_or(lines.len(), |n| i + n);
+        let block = &lines[i..end];
+        let next = lines.get(end).map_or(content.len(), |(next, _, _)| *next);
+        let closed = end < lines.len() || content.ends_with(b"\n");
+
+        if closed && next - start <= LONGEST_JOINED_COMMAND {
+            let raw_line = block
+                .iter()
+                .map(|(_, _, l)| l.as_ref())
+                .collect::<Vec<_>>()
+                .join("\n");
+            push(&raw_line, Some(timestamp));
+        } else {
+            let mut timestamp = Some(timestamp);
+            for (_, _, line) in block {
+                if !line.trim().is_empty() {
+                    push(line, timestamp.take());
                 }
-                continue;
             }
+        }
+        i = end;
+    }
+
+    entries
+}
+
+fn parse_fish(content: &[u8]) -> Vec<HistoryEntry> {
+    let text = String::from_utf8_lossy(content);
+    let mut entries: Vec<HistoryEntry> = Vec::new();
+
+    for line in text.lines() {
+        if let Some(cmd) = line.strip_prefix("- cmd: ") {
+            entries.push(HistoryEntry {
+                command: unescape_fish(cmd),
+                timestamp: None,
+                raw_line: Some(line.to_string()),
+                ..Default::default()
+            });
+        } else if let Some(when) = line.strip_prefix("  when: ") {
+            if let Some(entry) = entries.last_mut() {
+                entry.timestamp = when.trim().parse::<i64>().ok();
+            }
+        }
+        // `paths:` and its items only list arguments that were files; there
+        // is nothing in them the search needs.
+    }
+
+    entries.retain(|e| !e.command.trim().is_empty());
+    entries
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    Zsh,
+    Bash,
+    Fish,
+    /// ihistory's own log, as written by `ihistory __record`.
+    Ihistory,
+}
+
+/// How much of a file `detect_format` looks at. A few KB holds dozens of
+/// entries, plenty to tell the formats apart.
+const FORMAT_SNIFF_BYTES: usize = 4096;
+
+fn is_zsh_extended_line(line: &[u8]) -> bool {
+    // `: EPOCH:DURATION;`
+    let Some(rest) = line.strip_prefix(b": ") else {
+        return false;
+    };
+    let Some(semi) = rest.iter().position(|&b| b == b';') else {
+        return false;
+    };
+    let mut fields = rest[..semi].split(|&b| b == b':');
+    let is_number =
+        |f: Option<&[u8]>| f.is_some_and(|f| !f.is_empty() && f.iter().all(u8::is_ascii_digit));
+    is_number(fields.next()) && is_number(fields.next()) && fields.next().is_none()
+}
+
+/// File names zsh's own setup writes history to, for plain files that
+/// could have come from either shell.
+const ZSH_FILE_NAMES: &[&str] = &[".zsh_history", ".histfile"];
+
+/// Works out which shell wrote the history file at `path` from its
+/// contents. Each line of the sample votes for the format its shape is
+/// unique to; files with no telltale lines at all are plain
+/// one-command-per-line lists, which zsh and bash write identically except
+/// for zsh's metafied bytes and its `\`-continued lines. Only when the
+/// contents say nothing either way does the file name decide.
+pub fn detect_format(content: &[u8], path: &Path) -> HistoryFormat {
+    let sample = &content[..content.len().min(FORMAT_SNIFF_BYTES)];
+
+    let (mut zsh, mut bash, mut fish, mut ihistory) = (0, 0, 0, 0);
+    let mut continued = 0;
+    for line in sample.split(|&b| b == b'\n') {
+        if line.ends_with(b"\\") {
+            continued += 1;
+        }
+        if store::is_record_line(&String::from_utf8_lossy(line)) {
+            ihistory += 1;
+        } else if line.starts_with(b"- cmd: ") {
+            fish += 1;
+        } else if is_zsh_extended_line(line) {
+            zsh += 1;
+        } else if parse_bash_timestamp(&String::from_utf8_lossy(line)).is_some() {
+            bash += 1;
+        }
+    }
+
+    if ihistory > 0 && ihistory >= zsh.max(bash).max(fish) {
+        return HistoryFormat::Ihistory;
+    }
+    if fish > 0 && fish >= zsh && fish >= bash {
+        return HistoryFormat::Fish;
+    }
+    if zsh > 0 && zsh >= bash {
+        return HistoryFormat::Zsh;
+    }
+    if bash > 0 {
+        return HistoryFormat::Bash;
+    }
+
+    // The sample may end mid-character; only a genuinely invalid sequence
+    // counts against the raw bytes.
+    let is_valid = |bytes: &[u8]| match std::str::from_utf8(bytes) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    let metafied = !is_valid(sample) && is_valid(&unmetafy(sample));
+    let zsh_file_name = path
+        .file_name()
+        .is_some_and(|name| ZSH_FILE_NAMES.iter().any(|zsh| name == *zsh));
+    if metafied || continued > 0 || zsh_file_name {
+        HistoryFormat::Zsh
+    } else {
+        HistoryFormat::Bash
+    }
+}
+
+fn parse(content: &[u8], format: HistoryFormat) -> Vec<HistoryEntry> {
+    match format {
+        HistoryFormat::Zsh => parse_zsh(content),
+        HistoryFormat::Bash => parse_bash(content),
+        HistoryFormat::Fish => parse_fish(content),
+        HistoryFormat::Ihistory => store::parse_store(content),
+    }
+}
+
+/// Length of the longest prefix of `content` that ends between two
+/// records, so that parsing it and the rest separately gives the same
+/// entries as parsing the whole. What follows may be a record the shell
+/// is still writing, or one it will add lines to.
+fn complete_records_len(content: &[u8], format: HistoryFormat) -> usize {
+    let mut len = 0;
+    let mut last_bash_marker = None;
+    let mut start = 0;
+
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        let end = start + line.len();
+        let complete = line.ends_with(b"\n");
+        let text = line.strip_suffix(b"\n").unwrap_or(line);
+        match format {
+            HistoryFormat::Zsh if complete && !text.ends_with(b"\\") => len = end,
+            // After a timestamp marker, bash keeps adding lines to a
+            // command until the next marker.
+            HistoryFormat::Bash
+                if parse_bash_timestamp(&String::from_utf8_lossy(text)).is_some() =>
+            {
+                last_bash_marker = Some(start);
+            }
+            HistoryFormat::Bash | HistoryFormat::Ihistory if complete => len = end,
+            HistoryFormat::Fish if text.starts_with(b"- cmd: ") => len = start,
+            _ => {}
+        }
+        start = end;
+    }
+
+    last_bash_marker.unwrap_or(len)
+}
+
+/// Parses the history file at `path`, starting from the entries cached at
+/// `cache_path` by an earlier run when the file has only grown since, and
+/// updating the cache for the next one. With `until`, which must fall
+/// between two records, only the file's first `until` bytes are read.
+fn read_with_cache(
+    path: &Path,
+    format: Option<HistoryFormat>,
+    cache_path: Option<&Path>,
+    until: Option<u64>,
+) -> Result<Vec<HistoryEntry>, std::io::Error> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let modified = metadata.modified().ok();
+    let len = until.map_or(metadata.len(), |until| until.min(metadata.len()));
+
+    let cached = cache_path
+        .and_then(|cache_path| cache::load(cache_path, path))
+        .filter(|cached| format.is_none_or(|format| format == cached.format))
+        .filter(|cached| cached.offset <= len);
+    let unchanged = cached
+        .as_ref()
+        .is_some_and(|cached| cached.len == len && cached.modified == modified);
+
+    // Reading from a little before the cached offset takes in the bytes
+    // the fingerprint covers.
+    let mut read_from = cached.as_ref().map_or(0, |cached| {
+        cached.offset.saturating_sub(cache::FINGERPRINT_BYTES)
+    });
+    file.seek(SeekFrom::Start(read_from))?;
+    let mut content = Vec::new();
+    Read::take(&mut file, len - read_from).read_to_end(&mut content)?;
+
+    let resumed = cached.filter(|cached| {
+        let seen = (cached.offset - read_from) as usize;
+        content
+            .get(..seen)
+            .is_some_and(|seen| unchanged || cache::fingerprint(seen) == cached.fingerprint)
+    });
+    let (mut parsed, up_to_date) = match resumed {
+        Some(parsed) => (parsed, unchanged),
+        None => {
+            if read_from > 0 {
+                read_from = 0;
+                file.seek(SeekFrom::Start(0))?;
+                content.clear();
+                Read::take(&mut file, len).read_to_end(&mut content)?;
+            }
+            let format = format.unwrap_or_else(|| detect_format(&content, path));
+            (cache::ParsedFile::new(path, format), false)
+        }
+    };
+
+    let tail = &content[(parsed.offset - read_from) as usize..];
+    let complete = complete_records_len(tail, parsed.format);
+    let mut unfinished = parse(&tail[complete..], parsed.format);
+
+    if !up_to_date {
+        parsed
+            .entries
+            .extend(parse(&tail[..complete], parsed.format));
+        parsed.offset += complete as u64;
+        let end = (parsed.offset - read_from) as usize;
+        let start = end.saturating_sub(cache::FINGERPRINT_BYTES as usize);
+        parsed.fingerprint = cache::fingerprint(&content[start..end]);
+        parsed.len = len;
+        parsed.modified = modified;
+        // A cache that cannot be written only costs the next run time.
+        if let Some(cache_path) = cache_path {
+            let _ = cache::save(cache_path, &parsed);
+        }
+    }
+
+    let mut entries = parsed.entries;
+    entries.append(&mut unfinished);
+    Ok(entries)
+}
+
+fn read_source(source: &HistorySource) -> Result<Vec<HistoryEntry>, std::io::Error> {
+    read_source_until(source, source.format, None)
+}
 
-            if let Some(parsed) = parse_zsh_line(&line) {
-                if parsed.command.ends_with('\\') {
-                    multiline_buffer = Some((parsed.command, parsed.timestamp, parsed.raw_line));
-                } else {
-                    if entry_data.contains_key(&parsed.command) {
-                        order.retain(|c| c != &parsed.command);
+/// `read_source`, for the file's first `until` bytes only, as
+/// `read_with_cache` reads them.
+fn read_source_until(
+    source: &HistorySource,
+    format: Option<HistoryFormat>,
+    until: Option<u64>,
+) -> Result<Vec<HistoryEntry>, std::io::Error> {
+    let cache_path = cache::cache_path(&source.path);
+    let mut entries = read_with_cache(&source.path, format, cache_path.as_deref(), until)
+        .map_err(|e| std::io::Error::new(e.kind(), format!("{}: {}", source.path.display(), e)))?;
+
+    for entry in &mut entries {
+        entry.source = Some(source.label.clone());
+    }
+    Ok(entries)
+}
+
+/// Interleaves several files' entries into one oldest-to-newest timeline.
+///
+/// Entries are ordered by timestamp. An entry without one sorts with the
+/// latest timestamp seen before it in its own file (or as oldest of all,
+/// when none has been seen yet), so every file keeps its internal order
+/// even when its clock jumps backwards.
+fn merge_timelines(sources: Vec<Vec<HistoryEntry>>) -> Vec<HistoryEntry> {
+    if sources.len() == 1 {
+        return sources.into_iter().next().unwrap_or_default();
+    }
+
+    let mut keyed: Vec<(i64, HistoryEntry)> = Vec::new();
+    for entries in sources {
+        let keys: Vec<i64> = timeline_keys(&entries).collect();
+        keyed.extend(keys.into_iter().zip(entries));
+    }
+    // Stable, so ties keep file order and, across files, source order.
+    keyed.sort_by_key(|(key, _)| *key);
+    keyed.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// The keys `merge_timelines` sorts a file's entries by: the latest
+/// timestamp up to each.
+fn timeline_keys(entries: &[HistoryEntry]) -> impl Iterator<Item = i64> + '_ {
+    entries.iter().scan(i64::MIN, |latest, entry| {
+        if let Some(ts) = entry.timestamp {
+            *latest = (*latest).max(ts);
+        }
+        Some(*latest)
+    })
+}
+
+/// Where a command's entry is, and which of its runs are unpaired.
+#[derive(Debug)]
+struct Slot {
+    index: usize,
+    unpaired: Unpaired,
+}
+
+/// Runs folded into one entry per command, fed newest run first so that an
+/// entry's position reflects its most recent run. Hidden commands and
+/// ihistory's own invocations are left out, and at most `limit` distinct
+/// commands are kept (0 = unlimited).
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    positions: HashMap<String, Slot>,
+    blocklist: HashSet<String>,
+    limit: usize,
+}
+
+impl History {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            blocklist: load_blocklist(),
+            limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<HistoryEntry> {
+        self.entries
+    }
+
+    /// Adds runs older than any added so far, newest first. The first run
+    /// of a command becomes its entry; later ones are older duplicates,
+    /// from whichever file, and fold into it. A run that both a shell file
+    /// and ihistory's log recorded counts once. New entries are only ever
+    /// appended, so existing indices stay valid.
+    pub fn extend(&mut self, runs: impl IntoIterator<Item = HistoryEntry>) {
+        for run in runs {
+            let occurrence = Occurrence {
+                timestamp: run.timestamp,
+                raw_line: run.raw_line.clone(),
+                source: run.source.clone(),
+                cwd: run.cwd.clone(),
+                logged: run.logged,
+            };
+            match self.positions.get_mut(&run.command) {
+                Some(slot) => {
+                    let entry = &mut self.entries[slot.index];
+                    match slot.unpaired.take_match(&occurrence) {
+                        Some(same) => entry.occurrences[same].merge(occurrence),
+                        None => {
+                            slot.unpaired.add(&occurrence, entry.occurrences.len());
+                            entry.occurrences.push(occurrence);
+                        }
                     }
-                    order.push(parsed.command.clone());
-                    entry_data.insert(parsed.command, (parsed.timestamp, Some(parsed.raw_line)));
+                    entry.absorb(run);
+                }
+                None if self.blocklist.contains(&run.command) || is_self_command(&run.command) => {}
+                None if self.limit > 0 && self.entries.len() >= self.limit => {}
+                None => {
+                    let mut unpaired = Unpaired::default();
+                    unpaired.add(&occurrence, 0);
+                    let slot = Slot {
+                        index: self.entries.len(),
+                        unpaired,
+                    };
+                    self.positions.insert(run.command.clone(), slot);
+                    self.entries.push(HistoryEntry {
+                        occurrences: vec![occurrence],
+                        ..run
+                    });
                 }
             }
-        } else if let Some(command) = parse_bash_line(&line) {
-            if entry_data.contains_key(&command) {
-                order.retain(|c| c != &command);
+        }
+    }
+
+    /// Hides a command from ihistory's results. The shell's history file
+    /// is never modified — the command goes on a blocklist under the
+    /// config directory (a file named `deleted`, kept for compatibility
+    /// with earlier releases) and is filtered out on load.
+    pub fn hide(&mut self, index: usize) -> Result<(), std::io::Error> {
+        let command = self.entries[index].command.clone();
+        add_to_blocklist(&command)?;
+        self.entries.remove(index);
+        self.positions.remove(&command);
+        for slot in self.positions.values_mut() {
+            if slot.index > index {
+                slot.index -= 1;
             }
-            order.push(command.clone());
-            entry_data.insert(command, (None, Some(line.clone())));
         }
+        self.blocklist.insert(command);
+        Ok(())
     }
+}
 
-    order.reverse();
+/// Every run in the given history files, merged into one timeline, newest
+/// first.
+fn read_runs(sources: &[HistorySource]) -> Result<Vec<HistoryEntry>, std::io::Error> {
+    let parsed = sources
+        .par_iter()
+        .map(read_source)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut runs = merge_timelines(parsed);
+    runs.reverse();
+    Ok(runs)
+}
 
-    let blocklist = load_blocklist();
+/// Loads, merges and deduplicates the given history files, newest first.
+pub fn load_history(
+    sources: &[HistorySource],
+    limit: usize,
+) -> Result<Vec<HistoryEntry>, std::io::Error> {
+    let mut history = History::new(limit);
+    history.extend(read_runs(sources)?);
+    Ok(history.into_entries())
+}
 
-    let entries: Vec<HistoryEntry> = order
-        .into_iter()
-        .filter(|cmd| !blocklist.contains(cmd))
-        .filter(|cmd| !is_self_command(cmd))
-        .map(|command| {
-            let (timestamp, raw_line) = entry_data.remove(&command).unwrap_or((None, None));
-            HistoryEntry {
-                command,
-                timestamp,
-                raw_line,
+/// Runs per message sent by `load_in_background`.
+const LOAD_CHUNK: usize = 10_000;
+
+/// How much of the end of each file `load_in_background` reads first, so
+/// that the newest runs show while the rest loads.
+const TAIL_BYTES: u64 = 256 * 1024;
+
+/// Offset of the first record that starts in `content`, a piece cut from
+/// the middle of a file, so that parsing from there gives the entries the
+/// whole file has from there on. The first line may be cut short, so only
+/// its end is looked at.
+fn first_record_start(content: &[u8], format: HistoryFormat) -> usize {
+    let mut start = 0;
+    let mut first_line_end = None;
+    for (i, line) in content.split_inclusive(|&b| b == b'\n').enumerate() {
+        let end = start + line.len();
+        let complete = line.ends_with(b"\n");
+        let text = line.strip_suffix(b"\n").unwrap_or(line);
+        match format {
+            HistoryFormat::Zsh if complete && !text.ends_with(b"\\") => return end,
+            HistoryFormat::Fish if i > 0 && text.starts_with(b"- cmd: ") => return start,
+            // A command runs on until the next marker. Without one in
+            // sight, every line is a command of its own.
+            HistoryFormat::Bash
+                if i > 0 && parse_bash_timestamp(&String::from_utf8_lossy(text)).is_some() =>
+            {
+                return start;
             }
-        })
-        .collect();
+            HistoryFormat::Ihistory if complete => return end,
+            _ => {}
+        }
+        if complete && first_line_end.is_none() {
+            first_line_end = Some(end);
+        }
+        start = end;
+    }
+    match format {
+        HistoryFormat::Bash => first_line_end.unwrap_or(content.len()),
+        _ => content.len(),
+    }
+}
+
+/// The runs at the end of a file: those from `start`, where a record
+/// begins, on.
+struct Tail {
+    runs: Vec<HistoryEntry>,
+    start: u64,
+    format: HistoryFormat,
+}
+
+fn read_tail(source: &HistorySource) -> Result<Tail, std::io::Error> {
+    let mut file = File::open(&source.path)?;
+    let len = file.metadata()?.len();
+    let whole = len <= TAIL_BYTES;
 
-    let entries = if limit > 0 {
-        entries.into_iter().take(limit).collect()
+    // Formats are told apart by the start of the file, as `read_source`
+    // does.
+    let format = match source.format {
+        None if !whole => {
+            let mut sample = Vec::new();
+            Read::take(&mut file, FORMAT_SNIFF_BYTES as u64).read_to_end(&mut sample)?;
+            Some(detect_format(&sample, &source.path))
+        }
+        format => format,
+    };
+    let from = len.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(from))?;
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+
+    let format = format.unwrap_or_else(|| detect_format(&content, &source.path));
+    let start = if whole {
+        0
     } else {
-        entries
+        first_record_start(&content, format)
     };
+    let mut runs = parse(&content[start..], format);
+    for run in &mut runs {
+        run.source = Some(source.label.clone());
+    }
+    Ok(Tail {
+        runs,
+        start: from + start as u64,
+        format,
+    })
+}
 
-    Ok(entries)
+/// Splits off the runs at the end of each file that are newer than
+/// anything before them in any file, which can be shown before the rest is
+/// read. Only a file's own runs are kept in order, and the part of a file
+/// before its tail is taken to be no newer than the tail's first timestamp.
+fn newest_runs(tails: &mut [Option<Tail>]) -> Vec<Vec<HistoryEntry>> {
+    let cutoff = if tails.len() == 1 {
+        None
+    } else if tails.iter().any(Option::is_none) {
+        Some(i64::MAX)
+    } else {
+        tails
+            .iter()
+            .flatten()
+            .filter(|tail| tail.start > 0)
+            .map(|tail| {
+                tail.runs
+                    .iter()
+                    .find_map(|run| run.timestamp)
+                    .unwrap_or(i64::MAX)
+            })
+            .max()
+    };
+
+    tails
+        .iter_mut()
+        .map(|tail| {
+            let Some(tail) = tail else {
+                return Vec::new();
+            };
+            let split = match cutoff {
+                Some(cutoff) => timeline_keys(&tail.runs)
+                    .position(|key| key > cutoff)
+                    .unwrap_or(tail.runs.len()),
+                None => 0,
+            };
+            tail.runs.split_off(split)
+        })
+        .collect()
+}
+
+/// Sends `runs` in chunks, returning false once the UI no longer listens.
+fn send_chunks(
+    sender: &mpsc::Sender<Result<Vec<HistoryEntry>, std::io::Error>>,
+    runs: Vec<HistoryEntry>,
+) -> bool {
+    let mut runs = runs.into_iter().peekable();
+    while runs.peek().is_some() {
+        let chunk: Vec<HistoryEntry> = runs.by_ref().take(LOAD_CHUNK).collect();
+        if sender.send(Ok(chunk)).is_err() {
+            return false;
+        }
+    }
+    true
 }
 
-/// Hides a command from ihistory's results. The shell's history file is
-/// never modified — the command goes on a blocklist under the config
-/// directory (a file named `deleted`, kept for compatibility with earlier
-/// releases) and is filtered out on load.
-pub fn hide_entry(_path: &Path, entry: &HistoryEntry) -> Result<(), std::io::Error> {
-    add_to_blocklist(&entry.command)
+/// Chunks of runs, newest first, coming from `load_in_background`.
+pub type HistoryLoader = mpsc::Receiver<Result<Vec<HistoryEntry>, std::io::Error>>;
+
+/// Reads the given history files on a background thread and sends their
+/// runs newest first, in chunks to feed to `History::extend`. The end of
+/// each file is read first, so the newest runs arrive before the rest is
+/// parsed. The channel closes once everything is sent, or after the first
+/// error.
+pub fn load_in_background(sources: Vec<HistorySource>) -> HistoryLoader {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // A file whose end cannot be read is left for the full read to
+        // report.
+        let mut tails: Vec<Option<Tail>> = sources
+            .par_iter()
+            .map(|source| read_tail(source).ok())
+            .collect();
+        let mut newest = merge_timelines(newest_runs(&mut tails));
+        newest.reverse();
+        if !send_chunks(&sender, newest) {
+            return;
+        }
+        let done = tails.iter().all(|tail| {
+            tail.as_ref()
+                .is_some_and(|tail| tail.start == 0 && tail.runs.is_empty())
+        });
+        if done {
+            return;
+        }
+
+        // The rest of each file is what comes before its tail, followed by
+        // the tail's runs that were not sent.
+        let parsed = sources
+            .par_iter()
+            .zip(&mut tails)
+            .map(|(source, tail)| match tail {
+                Some(tail) => {
+                    let mut runs = match tail.start {
+                        0 => Vec::new(),
+                        start => read_source_until(source, Some(tail.format), Some(start))?,
+                    };
+                    runs.append(&mut tail.runs);
+                    Ok(runs)
+                }
+                None => read_source(source),
+            })
+            .collect::<Result<Vec<_>, std::io::Error>>();
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let _ = sender.send(Err(e));
+                return;
+            }
+        };
+        let mut runs = merge_timelines(parsed);
+        runs.reverse();
+        send_chunks(&sender, runs);
+    });
+    receiver
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
 
     #[test]
     fn test_parse_zsh_line_extended() {
@@ -311,8 +1169,574 @@ mod tests {
         bytes.extend([0xE6, 0x83, 0xB7, 0xA5, b'\n']);
         fs::write(&path, bytes).unwrap();
 
-        let entries = load_history(&path, 0).unwrap();
+        let entries = load_history(&[HistorySource::new(path, None, None)], 0).unwrap();
         fs::remove_dir_all(&dir).ok();
         assert!(entries.iter().any(|e| e.command == "echo 日"));
     }
+
+    #[test]
+    fn test_unescape_fish() {
+        assert_eq!(unescape_fish("echo a\\nb"), "echo a\nb");
+        assert_eq!(unescape_fish("printf '\\\\t'"), "printf '\\t'");
+        assert_eq!(unescape_fish("echo \\$HOME"), "echo \\$HOME");
+    }
+
+    #[test]
+    fn test_parse_fish_records() {
+        let content = concat!(
+            "- cmd: git status\n",
+            "  when: 1706500000\n",
+            "- cmd: cat src/main.rs\n",
+            "  when: 1706500100\n",
+            "  paths:\n",
+            "    - src/main.rs\n",
+            "- cmd: for f in *\\n  echo $f\\nend\n",
+            "  when: 1706500200\n",
+        );
+        let entries = parse_fish(content.as_bytes());
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].command, "git status");
+        assert_eq!(entries[0].timestamp, Some(1706500000));
+        assert_eq!(entries[1].command, "cat src/main.rs");
+        assert_eq!(entries[1].timestamp, Some(1706500100));
+        assert_eq!(entries[2].command, "for f in *\n  echo $f\nend");
+        assert_eq!(entries[2].timestamp, Some(1706500200));
+    }
+
+    #[test]
+    fn test_parse_bash_timestamp() {
+        assert_eq!(parse_bash_timestamp("#1706500000"), Some(1706500000));
+        assert_eq!(parse_bash_timestamp("# a comment"), None);
+        assert_eq!(parse_bash_timestamp("#"), None);
+        assert_eq!(parse_bash_timestamp("ls #1706500000"), None);
+    }
+
+    #[test]
+    fn test_parse_bash_attaches_timestamps() {
+        let content = b"ls\n#1706500000\ngit status\n#1706500100\ncargo build\n";
+        let entries = parse_bash(content);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].command, "ls");
+        assert_eq!(entries[0].timestamp, None);
+        assert_eq!(entries[1].command, "git status");
+        assert_eq!(entries[1].timestamp, Some(1706500000));
+        assert_eq!(entries[2].command, "cargo build");
+        assert_eq!(entries[2].timestamp, Some(1706500100));
+    }
+
+    #[test]
+    fn test_parse_bash_joins_lines_between_timestamps() {
+        let content = b"#1706500000\nfor f in *; do\n  echo $f\ndone\n#1706500100\nls\n";
+        let entries = parse_bash(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command, "for f in *; do\n  echo $f\ndone");
+        assert_eq!(entries[0].timestamp, Some(1706500000));
+        assert_eq!(entries[1].command, "ls");
+    }
+
+    #[test]
+    fn test_parse_bash_keeps_blank_lines_inside_entries() {
+        let content = b"#1706500000\nfor f in *; do\n\n  echo $f\ndone\n#1706500100\nls\n";
+        let entries = parse_bash(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command, "for f in *; do\n\n  echo $f\ndone");
+    }
+
+    #[test]
+    fn test_parse_bash_splits_lines_after_timestamps_stop() {
+        // HISTTIMEFORMAT was unset after the first command: a long stretch
+        // without markers is not one command.
+        let mut content = b"#1706500000\n".to_vec();
+        for n in 0..1000 {
+            content.extend(format!("echo {}\n", n).bytes());
+        }
+        assert_eq!(parse_bash(&content).len(), 1000);
+        content.extend(b"#1706500100\nls\n");
+        assert_eq!(parse_bash(&content).len(), 1001);
+    }
+
+    #[test]
+    fn test_parse_bash_joins_the_last_entry_once_it_is_complete() {
+        let content = b"#1706500000\nif true\nthen ls\nfi\n";
+        let entries = parse_bash(content);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "if true\nthen ls\nfi");
+
+        let content =
+            b"#1706500000\nif true\nthen ls\nfi\n#1706500100\nfor f in *\ndo echo\ndone\n";
+        let entries = parse_bash(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].command, "for f in *\ndo echo\ndone");
+        // Still being written.
+        assert_eq!(parse_bash(b"#1706500100\nfor f in *\ndo echo").len(), 2);
+    }
+
+    #[test]
+    fn test_detect_format_zsh_extended() {
+        let content = b": 1706500000:0;git status\n: 1706500010:2;cargo build\n";
+        assert_eq!(
+            detect_format(content, Path::new("hist.txt")),
+            HistoryFormat::Zsh
+        );
+    }
+
+    #[test]
+    fn test_detect_format_bash_timestamps() {
+        let content = b"#1706500000\ngit status\n#1706500010\ncargo build\n";
+        assert_eq!(
+            detect_format(content, Path::new("hist.txt")),
+            HistoryFormat::Bash
+        );
+    }
+
+    #[test]
+    fn test_detect_format_fish() {
+        let content = b"- cmd: git status\n  when: 1706500000\n";
+        assert_eq!(
+            detect_format(content, Path::new("hist.txt")),
+            HistoryFormat::Fish
+        );
+    }
+
+    #[test]
+    fn test_detect_format_plain_is_bash() {
+        assert_eq!(
+            detect_format(b"ls -la\ncd ~\n", Path::new("hist.txt")),
+            HistoryFormat::Bash
+        );
+        assert_eq!(
+            detect_format(b"", Path::new("hist.txt")),
+            HistoryFormat::Bash
+        );
+    }
+
+    #[test]
+    fn test_detect_format_plain_metafied_is_zsh() {
+        let content = [b'e', b'c', b'h', b'o', b' ', 0xE6, 0x83, 0xB7, 0xA5, b'\n'];
+        assert_eq!(
+            detect_format(&content, Path::new("hist.txt")),
+            HistoryFormat::Zsh
+        );
+    }
+
+    #[test]
+    fn test_detect_format_plain_zsh_by_continuations_or_name() {
+        let content = b"ls\nfor f in *; do\\\n  echo $f\\\ndone\ngit status\n";
+        assert_eq!(
+            detect_format(content, Path::new("hist.txt")),
+            HistoryFormat::Zsh
+        );
+        let entries = parse(content, HistoryFormat::Zsh);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].command, "for f in *; do\\\n  echo $f\\\ndone");
+
+        let plain = b"ls -la\ncd ~\n";
+        assert_eq!(
+            detect_format(plain, Path::new("/home/me/.zsh_history")),
+            HistoryFormat::Zsh
+        );
+        assert_eq!(
+            detect_format(plain, Path::new(".histfile")),
+            HistoryFormat::Zsh
+        );
+        assert_eq!(
+            detect_format(plain, Path::new(".bash_history")),
+            HistoryFormat::Bash
+        );
+        // What the contents say beats the name.
+        let bash = b"#1706500000\nls -la\n";
+        assert_eq!(
+            detect_format(bash, Path::new(".zsh_history")),
+            HistoryFormat::Bash
+        );
+    }
+
+    #[test]
+    fn test_load_history_ignores_file_name() {
+        let dir = std::env::temp_dir().join(format!("ihistory-test-fmt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hist.txt");
+        fs::write(&path, b": 1706500000:0;git status\n").unwrap();
+
+        let entries = load_history(&[HistorySource::new(path, None, None)], 0).unwrap();
+        fs::remove_dir_all(&dir).ok();
+        assert_eq!(entries[0].command, "git status");
+        assert_eq!(entries[0].timestamp, Some(1706500000));
+    }
+
+    fn entry(command: &str, timestamp: Option<i64>, source: &str) -> HistoryEntry {
+        HistoryEntry {
+            command: command.to_string(),
+            timestamp,
+            source: Some(source.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_timelines_interleaves_by_timestamp() {
+        let home = vec![entry("a", Some(10), "home"), entry("c", Some(30), "home")];
+        let server = vec![
+            entry("b", Some(20), "server"),
+            entry("d", Some(40), "server"),
+        ];
+        let merged = merge_timelines(vec![home, server]);
+        let commands: Vec<&str> = merged.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_merge_timelines_keeps_untimestamped_entries_in_place() {
+        let zsh = vec![entry("a", Some(10), "zsh"), entry("b", None, "zsh")];
+        let bash = vec![entry("plain", None, "bash"), entry("c", Some(20), "bash")];
+        let merged = merge_timelines(vec![zsh, bash]);
+        let commands: Vec<&str> = merged.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, ["plain", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_first_record_start_skips_the_cut_record() {
+        let zsh = b"o \\\nb\n: 2:0;c\n";
+        assert_eq!(first_record_start(zsh, HistoryFormat::Zsh), 6);
+        let bash = b"500000\nls\n#1706500100\npwd\n";
+        assert_eq!(first_record_start(bash, HistoryFormat::Bash), 10);
+        let plain = b"s -la\nls\npwd\n";
+        assert_eq!(first_record_start(plain, HistoryFormat::Bash), 6);
+        let fish = b"  when: 1\n- cmd: ls\n";
+        assert_eq!(first_record_start(fish, HistoryFormat::Fish), 10);
+    }
+
+    #[test]
+    fn test_tail_and_head_split_inside_a_multi_line_entry() {
+        let dir = std::env::temp_dir().join(format!("ihistory-test-split-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let zsh = |i: u64| {
+            format!(
+                ": {}:0;for f in *; do \\\n  echo {:06}\\\ndone\n",
+                1_000_000 + i,
+                i
+            )
+        };
+        let bash = |i: u64| format!("#{}\nif true\nthen echo {:06}\nfi\n", 1_000_000 + i, i);
+
+        for (name, entry) in [("zsh", &zsh as &dyn Fn(u64) -> String), ("bash", &bash)] {
+            let path = dir.join(name);
+            let count = TAIL_BYTES / entry(0).len() as u64 + 100;
+            // A command to start with, long enough that the tail is cut
+            // just past the first line of an entry.
+            let len = entry(0).len() as u64;
+            let cut = entry(0).find('\n').unwrap() as u64 + 2;
+            let mut padding = (cut + TAIL_BYTES) % len;
+            if padding < 2 {
+                padding += len;
+            }
+            let mut content = format!("{}\n", "x".repeat(padding as usize - 1));
+            content.extend((0..count).map(entry));
+            fs::write(&path, &content).unwrap();
+            assert_eq!((content.len() as u64 - TAIL_BYTES) % len, cut, "{}", name);
+
+            let source = HistorySource::new(path, None, None);
+            let tail = read_tail(&source).unwrap();
+            let mut runs = read_source_until(&source, Some(tail.format), Some(tail.start)).unwrap();
+            runs.extend(tail.runs);
+
+            let key = |run: &HistoryEntry| (run.command.clone(), run.timestamp);
+            let whole: Vec<_> = read_source(&source).unwrap().iter().map(key).collect();
+            assert_eq!(whole.len(), count as usize + 1, "{}", name);
+            assert_eq!(runs.iter().map(key).collect::<Vec<_>>(), whole, "{}", name);
+        }
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_load_in_background_sends_what_a_full_read_finds() {
+        let dir = std::env::temp_dir().join(format!("ihistory-test-tail-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let zsh = dir.join("zsh");
+        let bash = dir.join("bash");
+        let mut zsh_content = String::new();
+        let mut bash_content = String::new();
+        for i in 0..20_000 {
+            zsh_content.push_str(&format!(": {}:0;make \\\n  target-{}\n", 1000 + 2 * i, i));
+            bash_content.push_str(&format!(
+                "#{}\nif true\nthen echo {}\nfi\n",
+                1001 + 2 * i,
+                i % 50
+            ));
+        }
+        // Many identical runs to end with.
+        for i in 0..100 {
+            zsh_content.push_str(&format!(": {}:0;ls\n", 50_000 + i));
+        }
+        fs::write(&zsh, zsh_content).unwrap();
+        fs::write(&bash, bash_content).unwrap();
+
+        let sources = vec![
+            HistorySource::new(zsh, None, None),
+            HistorySource::new(bash, None, None),
+        ];
+        let key = |run: &HistoryEntry| (run.command.clone(), run.timestamp);
+        let expected: Vec<_> = read_runs(&sources).unwrap().iter().map(key).collect();
+        let batches: Vec<Vec<HistoryEntry>> = load_in_background(sources)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        fs::remove_dir_all(&dir).ok();
+
+        // The newest runs come in batches of their own, ahead of the rest.
+        assert!(batches[..batches.len() - 1]
+            .iter()
+            .any(|batch| batch.len() < LOAD_CHUNK));
+        let sent: Vec<_> = batches.iter().flatten().map(key).collect();
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn test_load_history_dedupes_across_sources() {
+        let dir = std::env::temp_dir().join(format!("ihistory-test-merge-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let zsh = dir.join("zsh");
+        let bash = dir.join("bash");
+        fs::write(&zsh, b": 100:0;git status\n: 300:0;make\n").unwrap();
+        fs::write(&bash, b"#200\nmake\n#400\ngit status\n").unwrap();
+
+        let sources = [
+            HistorySource::new(zsh, None, Some("home".to_string())),
+            HistorySource::new(bash, None, Some("server".to_string())),
+        ];
+        let entries = load_history(&sources, 0).unwrap();
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].command, "git status");
+        assert_eq!(entries[0].source.as_deref(), Some("server"));
+        assert_eq!(entries[0].run_count(), 2);
+        assert_eq!(entries[0].first_seen(), Some(100));
+        let runs: Vec<Option<i64>> = entries[0].occurrences.iter().map(|o| o.timestamp).collect();
+        assert_eq!(runs, [Some(400), Some(100)]);
+        assert_eq!(entries[1].command, "make");
+        assert_eq!(entries[1].timestamp, Some(300));
+        assert_eq!(entries[1].source.as_deref(), Some("home"));
+    }
+
+    #[test]
+    fn test_detect_format_ihistory_log() {
+        let content = b"1706500000\t12\t0\ts\th\t/tmp\tls -la\n";
+        assert_eq!(
+            detect_format(content, Path::new("hist.txt")),
+            HistoryFormat::Ihistory
+        );
+    }
+
+    #[test]
+    fn test_load_history_keeps_recorded_metadata_for_shell_duplicates() {
+        let dir = std::env::temp_dir().join(format!("ihistory-test-store-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let zsh = dir.join("zsh");
+        let log = dir.join("log");
+        // The shell stamped the command a second after the hook did.
+        fs::write(&zsh, b": 101:0;make test\n").unwrap();
+        fs::write(&log, b"100\t2300\t1\ts\th\t/src/app\tmake test\n").unwrap();
+
+        let sources = [
+            HistorySource::new(zsh, None, None),
+            HistorySource::new(log, None, None),
+        ];
+        let entries = load_history(&sources, 0).unwrap();
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].run_count(), 1);
+        assert_eq!(entries[0].timestamp, Some(101));
+        assert_eq!(entries[0].cwd, Some(PathBuf::from("/src/app")));
+        assert_eq!(entries[0].exit_code, Some(1));
+        assert_eq!(entries[0].duration_ms, Some(2300));
+    }
+
+    fn run(command: &str, timestamp: i64) -> HistoryEntry {
+        HistoryEntry {
+            command: command.to_string(),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_history_counts_a_run_in_two_files_once() {
+        let logged = |timestamp| HistoryEntry {
+            logged: true,
+            ..run("make", timestamp)
+        };
+        let mut history = History::default();
+        history.extend([
+            run("make", 1001),
+            logged(1000),
+            run("make", 500),
+            logged(499),
+            // Two runs in a row in one file stay two runs.
+            run("make", 498),
+        ]);
+        assert_eq!(history.entries()[0].run_count(), 3);
+
+        // Two shell files are two machines, whose runs all count.
+        let mut history = History::default();
+        history.extend([run("make", 1001), run("make", 1000)]);
+        assert_eq!(history.entries()[0].run_count(), 2);
+    }
+
+    #[test]
+    fn test_history_pairs_untimed_shell_runs_with_the_log() {
+        let untimed = HistoryEntry {
+            command: "make".to_string(),
+            ..Default::default()
+        };
+        let logged = |timestamp| HistoryEntry {
+            logged: true,
+            cwd: Some(PathBuf::from("/src/app")),
+            ..run("make", timestamp)
+        };
+        let mut history = History::default();
+        history.extend([
+            logged(2000),
+            logged(1000),
+            untimed.clone(),
+            untimed.clone(),
+            untimed,
+        ]);
+
+        let entry = &history.entries()[0];
+        assert_eq!(entry.run_count(), 3);
+        assert_eq!(entry.occurrences[0].cwd, Some(PathBuf::from("/src/app")));
+    }
+
+    #[test]
+    fn test_history_folds_many_runs_of_one_command() {
+        // Each run is only compared with runs a few seconds from it.
+        let runs = (0..200_000).rev().flat_map(|i| {
+            [
+                run("make", 10 * i + 1),
+                HistoryEntry {
+                    logged: true,
+                    ..run("make", 10 * i)
+                },
+            ]
+        });
+        let mut history = History::default();
+        history.extend(runs);
+        assert_eq!(history.entries()[0].run_count(), 200_000);
+    }
+
+    #[test]
+    fn test_history_folds_older_runs_across_chunks() {
+        let mut history = History::default();
+        history.extend([run("make", 300), run("ls", 200)]);
+        history.extend([run("make", 100), run("ih foo", 50), run("cd /", 10)]);
+
+        let entries = history.entries();
+        let commands: Vec<&str> = entries.iter().map(|e| e.command.as_str()).collect();
+        assert_eq!(commands, ["make", "ls", "cd /"]);
+        assert_eq!(entries[0].run_count(), 2);
+        assert_eq!(entries[0].timestamp, Some(300));
+        assert_eq!(entries[0].first_seen(), Some(100));
+    }
+
+    #[test]
+    fn test_history_limit_still_counts_older_runs() {
+        let mut history = History {
+            limit: 1,
+            ..Default::default()
+        };
+        history.extend([run("make", 300), run("ls", 200), run("make", 100)]);
+        assert_eq!(history.entries().len(), 1);
+        assert_eq!(history.entries()[0].run_count(), 2);
+    }
+
+    #[test]
+    fn test_complete_records_len_splits_between_records() {
+        let cases: [(HistoryFormat, &str, &str); 5] = [
+            (
+                HistoryFormat::Zsh,
+                ": 1:0;ls\n: 2:0;for f in *; do\\\necho $f\\\n",
+                ": 1:0;ls\n",
+            ),
+            (HistoryFormat::Bash, "ls\ncd /\ngit st", "ls\ncd /\n"),
+            (
+                HistoryFormat::Bash,
+                "#1\nls\n#2\ncat <<EOF\nhi\n",
+                "#1\nls\n",
+            ),
+            (
+                HistoryFormat::Fish,
+                "- cmd: ls\n  when: 1\n- cmd: cd /\n  when: 2\n",
+                "- cmd: ls\n  when: 1\n",
+            ),
+            (
+                HistoryFormat::Ihistory,
+                "1\t\t\t\t\t\tls\n2\t\t",
+                "1\t\t\t\t\t\tls\n",
+            ),
+        ];
+        for (format, content, complete) in cases {
+            let len = complete_records_len(content.as_bytes(), format);
+            assert_eq!(&content[..len], complete, "{:?}", format);
+        }
+    }
+
+    fn summary(entries: &[HistoryEntry]) -> Vec<(String, Option<i64>)> {
+        entries
+            .iter()
+            .map(|e| (e.command.clone(), e.timestamp))
+            .collect()
+    }
+
+    #[test]
+    fn test_read_with_cache_parses_only_appended_tail() {
+        let dir = std::env::temp_dir().join(format!("ihistory-test-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history");
+        let cache = dir.join("cache.bin");
+        let append = |bytes: &[u8]| {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(bytes).unwrap();
+        };
+
+        fs::write(&path, b": 1:0;ls\n: 2:0;echo a\\\n").unwrap();
+        let first = read_with_cache(&path, None, Some(&cache), None).unwrap();
+        let offset = cache::load(&cache, &path).unwrap().offset;
+        assert_eq!(offset, 9);
+
+        append(b"b\n: 3:0;make\n");
+        let second = read_with_cache(&path, None, Some(&cache), None).unwrap();
+        let cached = cache::load(&cache, &path).unwrap();
+
+        // Rewritten rather than appended to: the fingerprint no longer matches.
+        fs::write(&path, b": 7:0;pwd\n: 8:0;echo b\\\nb\n: 9:0;make\n").unwrap();
+        let rewritten = read_with_cache(&path, None, Some(&cache), None).unwrap();
+        fs::write(&path, b": 5:0;top\n").unwrap();
+        let truncated = read_with_cache(&path, None, Some(&cache), None).unwrap();
+        fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(
+            summary(&first),
+            [("ls".into(), Some(1)), ("echo a\\\n".into(), Some(2))]
+        );
+        assert_eq!(cached.entries.len(), 3);
+        assert_eq!(
+            summary(&second),
+            [
+                ("ls".into(), Some(1)),
+                ("echo a\\\nb".into(), Some(2)),
+                ("make".into(), Some(3))
+            ]
+        );
+        assert_eq!(
+            summary(&rewritten),
+            [
+                ("pwd".into(), Some(7)),
+                ("echo b\\\nb".into(), Some(8)),
+                ("make".into(), Some(9))
+            ]
+        );
+        assert_eq!(summary(&truncated), [("top".into(), Some(5))]);
+    }
 }
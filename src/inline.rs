@@ -0,0 +1,121 @@
+//! Drawing below the prompt instead of taking over the screen.
+//!
+//! ratatui's own inline viewport finds the cursor through crossterm, which
+//! asks the terminal on stdout; inside `$(…)` that question goes into the
+//! pipe and is never answered. Here it goes to the tty instead, and the
+//! search is drawn in a fixed area just under the prompt line.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::terminal;
+use ratatui::layout::{Position, Rect};
+use rustix::event::{poll, PollFd, PollFlags};
+
+/// Fewer rows than this leave no room for results.
+pub const MIN_ROWS: u16 = 8;
+
+/// How long to wait for the terminal to say where the cursor is. Every
+/// terminal answers at once; this only stops ihistory hanging on one that
+/// never does.
+const REPORT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The cursor's column and row, counted from zero, as the terminal reports
+/// it. Raw mode has to be on, or the report would be echoed.
+fn cursor_position(tty: &mut File) -> io::Result<(u16, u16)> {
+    tty.write_all(b"\x1b[6n")?;
+    tty.flush()?;
+
+    let deadline = Instant::now() + REPORT_TIMEOUT;
+    let mut response = Vec::new();
+    loop {
+        if let Some(position) = parse_cursor_report(&response) {
+            return Ok(position);
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(&*tty, PollFlags::IN)];
+        if remaining.is_zero() || poll(&mut fds, remaining.as_millis() as i32)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "The terminal did not report the cursor position",
+            ));
+        }
+        let mut buf = [0; 32];
+        let n = tty.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        response.extend_from_slice(&buf[..n]);
+    }
+}
+
+/// Finds `ESC [ row ; column R` in what the terminal sent, skipping any
+/// keys typed just before it.
+fn parse_cursor_report(bytes: &[u8]) -> Option<(u16, u16)> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    let start = text.rfind("\x1b[")?;
+    let (row, column) = text[start + 2..].strip_suffix('R')?.split_once(';')?;
+    let row: u16 = row.parse().ok()?;
+    let column: u16 = column.parse().ok()?;
+    Some((column.saturating_sub(1), row.saturating_sub(1)))
+}
+
+/// Makes room for `rows` rows under the cursor's line, scrolling the
+/// screen up when the cursor is too close to the bottom. Returns the area
+/// to draw in and where the cursor belongs once the search is gone.
+pub fn make_room(tty: &mut File, rows: u16) -> io::Result<(Rect, Position)> {
+    let (column, row) = cursor_position(tty)?;
+    let (width, height) = terminal::size()?;
+    // In raw mode a line feed only moves down, scrolling at the bottom.
+    tty.write_all("\n".repeat(rows as usize).as_bytes())?;
+    tty.flush()?;
+    let top = viewport_top(row, rows, height);
+    Ok((
+        Rect::new(0, top, width, rows),
+        Position::new(column, top - 1),
+    ))
+}
+
+/// The first row under the prompt on `row`, once `rows` line feeds have
+/// scrolled as much as they had to.
+fn viewport_top(row: u16, rows: u16, height: u16) -> u16 {
+    (row + 1).min(height.saturating_sub(rows))
+}
+
+/// The area after a resize: as wide as the terminal, and as tall as it
+/// was while that still fits.
+pub fn resized(area: Rect, width: u16, height: u16) -> Rect {
+    let rows = area.height.min(height.saturating_sub(1)).max(1);
+    Rect::new(0, area.y.min(height.saturating_sub(rows)), width, rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cursor_reports() {
+        assert_eq!(parse_cursor_report(b"\x1b[12;5R"), Some((4, 11)));
+        assert_eq!(parse_cursor_report(b"ab\x1b[1;1R"), Some((0, 0)));
+        assert_eq!(parse_cursor_report(b"\x1b[12;5"), None);
+        assert_eq!(parse_cursor_report(b""), None);
+    }
+
+    #[test]
+    fn scrolls_only_when_the_prompt_is_near_the_bottom() {
+        // Room to spare below row 5 of 40.
+        assert_eq!(viewport_top(5, 10, 40), 6);
+        // On the last row, the screen scrolls up by the whole height.
+        assert_eq!(viewport_top(39, 10, 40), 30);
+        assert_eq!(viewport_top(33, 10, 40), 30);
+    }
+
+    #[test]
+    fn keeps_the_area_on_screen_after_a_resize() {
+        let area = Rect::new(0, 30, 80, 10);
+        assert_eq!(resized(area, 100, 40), Rect::new(0, 30, 100, 10));
+        assert_eq!(resized(area, 100, 35), Rect::new(0, 25, 100, 10));
+        assert_eq!(resized(area, 100, 8), Rect::new(0, 1, 100, 7));
+    }
+}
@@ -0,0 +1,492 @@
+//! Key bindings: what each key does, as data the config can override.
+//!
+//! Keys are written the way they read, `ctrl-d`, `shift-tab`, `G`, and a
+//! sequence is several of them separated by spaces, as in vi's `g g`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use serde::{Deserialize, Deserializer};
+
+/// Everything a key can be bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    /// Leave without picking anything.
+    Quit,
+    /// Put the selection on the prompt.
+    Accept,
+    /// Run the selection straight away.
+    Execute,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    HalfPageUp,
+    HalfPageDown,
+    First,
+    Last,
+    Mark,
+    Hide,
+    CycleMode,
+    CycleScope,
+    /// Scroll a long command in the preview.
+    PreviewUp,
+    PreviewDown,
+    /// Empty the query.
+    Clear,
+    Left,
+    Right,
+    Home,
+    End,
+    WordLeft,
+    WordRight,
+    Backspace,
+    Delete,
+    DeleteWord,
+    DeleteToStart,
+    /// Switch to vi's normal mode; does nothing under the emacs keymap.
+    NormalMode,
+    /// Switch back to vi's insert mode.
+    InsertMode,
+    /// Takes a default binding away.
+    #[serde(rename = "none")]
+    Unbound,
+}
+
+impl Action {
+    /// The same move the other way up the screen, for results listed from
+    /// the bottom.
+    pub fn mirrored(self) -> Self {
+        match self {
+            Action::Up => Action::Down,
+            Action::Down => Action::Up,
+            Action::PageUp => Action::PageDown,
+            Action::PageDown => Action::PageUp,
+            Action::HalfPageUp => Action::HalfPageDown,
+            Action::HalfPageDown => Action::HalfPageUp,
+            Action::First => Action::Last,
+            Action::Last => Action::First,
+            action => action,
+        }
+    }
+}
+
+/// One key press: a key and the modifiers held with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    /// The chord for a key event. Terminals disagree on whether Shift is
+    /// reported alongside a character that already shows it (`G`) or with
+    /// Shift-Tab, so it is dropped from both.
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+        Self { code, modifiers }
+    }
+
+    /// The character this chord types into the query, if it is plain text.
+    pub fn typed_char(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c) if self.modifiers.is_empty() => Some(c),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s;
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            let (modifier, len) = if lower.starts_with("ctrl-") {
+                (KeyModifiers::CONTROL, 5)
+            } else if lower.starts_with("alt-") {
+                (KeyModifiers::ALT, 4)
+            } else if lower.starts_with("shift-") {
+                (KeyModifiers::SHIFT, 6)
+            } else {
+                break;
+            };
+            modifiers |= modifier;
+            rest = &rest[len..];
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "esc" | "escape" => KeyCode::Esc,
+                "enter" | "return" => KeyCode::Enter,
+                "tab" => KeyCode::Tab,
+                "space" => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "delete" | "del" => KeyCode::Delete,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key `{}` in `{}`", rest, s)),
+                },
+            },
+        };
+
+        // Shift is spelled out in the character itself, and turns Tab
+        // into the BackTab terminals send.
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                KeyCode::Char(c.to_uppercase().next().unwrap_or(c))
+            }
+            KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            code => code,
+        };
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "S-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            // Ctrl+D, as terminals and manuals write it.
+            KeyCode::Char(c) if self.modifiers.contains(KeyModifiers::CONTROL) => {
+                write!(f, "{}", c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::Esc => write!(f, "Esc"),
+            KeyCode::Enter => write!(f, "Enter"),
+            KeyCode::Tab => write!(f, "Tab"),
+            KeyCode::BackTab => write!(f, "S-Tab"),
+            KeyCode::Backspace => write!(f, "Bksp"),
+            KeyCode::Delete => write!(f, "Del"),
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::Left => write!(f, "←"),
+            KeyCode::Right => write!(f, "→"),
+            KeyCode::Home => write!(f, "Home"),
+            KeyCode::End => write!(f, "End"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+/// Keys pressed one after another, like vi's `gg`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeySequence(Vec<KeyChord>);
+
+impl FromStr for KeySequence {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chords = s
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<KeyChord>, _>>()?;
+        if chords.is_empty() {
+            return Err("empty key".to_string());
+        }
+        Ok(Self(chords))
+    }
+}
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for chord in &self.0 {
+            write!(f, "{}", chord)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `[keys]` table: each key parsed up front, so that a typo is reported
+/// at startup rather than silently ignored.
+pub fn deserialize_overrides<'de, D>(
+    deserializer: D,
+) -> Result<Vec<(KeySequence, Action)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let table = BTreeMap::<String, Action>::deserialize(deserializer)?;
+    table
+        .into_iter()
+        .map(|(keys, action)| Ok((keys.parse().map_err(serde::de::Error::custom)?, action)))
+        .collect()
+}
+
+/// What the keys pressed so far add up to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    Action(Action),
+    /// The start of a longer sequence; wait for the next key.
+    Prefix,
+    Unbound,
+}
+
+const EMACS: &[(&str, Action)] = &[
+    ("esc", Action::Quit),
+    ("ctrl-c", Action::Quit),
+    ("enter", Action::Accept),
+    ("tab", Action::Execute),
+    ("shift-tab", Action::Mark),
+    ("ctrl-space", Action::Mark),
+    ("up", Action::Up),
+    ("ctrl-p", Action::Up),
+    ("down", Action::Down),
+    ("ctrl-n", Action::Down),
+    ("pageup", Action::PageUp),
+    ("pagedown", Action::PageDown),
+    ("shift-up", Action::PreviewUp),
+    ("shift-down", Action::PreviewDown),
+    ("ctrl-d", Action::Hide),
+    ("ctrl-t", Action::CycleMode),
+    ("ctrl-g", Action::CycleScope),
+    ("left", Action::Left),
+    ("right", Action::Right),
+    ("home", Action::Home),
+    ("ctrl-a", Action::Home),
+    ("end", Action::End),
+    ("ctrl-e", Action::End),
+    ("alt-b", Action::WordLeft),
+    ("alt-f", Action::WordRight),
+    ("backspace", Action::Backspace),
+    ("delete", Action::Delete),
+    ("ctrl-w", Action::DeleteWord),
+    ("ctrl-u", Action::DeleteToStart),
+];
+
+/// Vi's normal mode. Insert mode is the emacs keymap with `Esc` leading
+/// here instead of out.
+const VI_NORMAL: &[(&str, Action)] = &[
+    ("q", Action::Quit),
+    ("ctrl-c", Action::Quit),
+    ("/", Action::InsertMode),
+    ("i", Action::InsertMode),
+    ("a", Action::InsertMode),
+    ("j", Action::Down),
+    ("down", Action::Down),
+    ("k", Action::Up),
+    ("up", Action::Up),
+    ("g g", Action::First),
+    ("G", Action::Last),
+    ("ctrl-d", Action::HalfPageDown),
+    ("ctrl-u", Action::HalfPageUp),
+    ("pageup", Action::PageUp),
+    ("pagedown", Action::PageDown),
+    ("shift-up", Action::PreviewUp),
+    ("shift-down", Action::PreviewDown),
+    ("enter", Action::Accept),
+    ("tab", Action::Execute),
+    ("space", Action::Mark),
+    ("shift-tab", Action::Mark),
+    ("ctrl-space", Action::Mark),
+    ("d d", Action::Hide),
+    ("ctrl-t", Action::CycleMode),
+    ("ctrl-g", Action::CycleScope),
+];
+
+/// Key sequences and their actions, in order of preference: where an
+/// action has several keys, the help bar shows the first.
+#[derive(Debug, Clone)]
+pub struct Bindings(Vec<(KeySequence, Action)>);
+
+impl Bindings {
+    fn from_table(table: &[(&str, Action)]) -> Self {
+        Self(
+            table
+                .iter()
+                .map(|&(keys, action)| (keys.parse().expect("default binding"), action))
+                .collect(),
+        )
+    }
+
+    pub fn emacs() -> Self {
+        Self::from_table(EMACS)
+    }
+
+    pub fn vi_insert() -> Self {
+        let mut bindings = Self::emacs();
+        bindings.bind("esc".parse().expect("default binding"), Action::NormalMode);
+        bindings
+    }
+
+    pub fn vi_normal() -> Self {
+        Self::from_table(VI_NORMAL)
+    }
+
+    /// Binds `keys` in place of whatever they did before; `Unbound` just
+    /// takes them away.
+    pub fn bind(&mut self, keys: KeySequence, action: Action) {
+        match self.0.iter().position(|(k, _)| *k == keys) {
+            Some(i) if action == Action::Unbound => {
+                self.0.remove(i);
+            }
+            Some(i) => self.0[i].1 = action,
+            None if action == Action::Unbound => {}
+            None => self.0.push((keys, action)),
+        }
+    }
+
+    pub fn with_overrides(mut self, overrides: &[(KeySequence, Action)]) -> Self {
+        for (keys, action) in overrides {
+            self.bind(keys.clone(), *action);
+        }
+        self
+    }
+
+    pub fn lookup(&self, pressed: &[KeyChord]) -> Lookup {
+        let mut prefix = false;
+        for (keys, action) in &self.0 {
+            if keys.0 == pressed {
+                return Lookup::Action(*action);
+            }
+            prefix |= keys.0.starts_with(pressed);
+        }
+        if prefix {
+            Lookup::Prefix
+        } else {
+            Lookup::Unbound
+        }
+    }
+
+    /// The preferred keys for `action`, if any are bound to it.
+    pub fn keys_for(&self, action: Action) -> Option<&KeySequence> {
+        self.0.iter().find(|(_, a)| *a == action).map(|(k, _)| k)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(s: &str) -> KeyChord {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn defaults_parse() {
+        Bindings::emacs();
+        Bindings::vi_insert();
+        Bindings::vi_normal();
+    }
+
+    #[test]
+    fn parses_chords() {
+        assert_eq!(
+            chord("ctrl-d"),
+            KeyChord::new(KeyCode::Char('d'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            chord("Ctrl-Alt-x"),
+            KeyChord::new(
+                KeyCode::Char('x'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            )
+        );
+        assert_eq!(
+            chord("shift-tab"),
+            KeyChord::new(KeyCode::BackTab, KeyModifiers::SHIFT)
+        );
+        assert_eq!(chord("shift-g"), chord("G"));
+        assert_eq!(
+            chord("shift-up"),
+            KeyChord::new(KeyCode::Up, KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            chord("-"),
+            KeyChord::new(KeyCode::Char('-'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            chord("F5"),
+            KeyChord::new(KeyCode::F(5), KeyModifiers::NONE)
+        );
+        assert!("ctrl-".parse::<KeyChord>().is_err());
+        assert!("hyper-x".parse::<KeyChord>().is_err());
+        assert!("f13".parse::<KeyChord>().is_err());
+        assert!("".parse::<KeySequence>().is_err());
+    }
+
+    #[test]
+    fn matches_events_however_shift_is_reported() {
+        let event = KeyChord::new(KeyCode::Char('G'), KeyModifiers::SHIFT);
+        assert_eq!(event, chord("G"));
+        assert_eq!(event.typed_char(), Some('G'));
+    }
+
+    #[test]
+    fn looks_up_sequences() {
+        let bindings = Bindings::vi_normal();
+        let g = chord("g");
+        assert_eq!(bindings.lookup(&[g]), Lookup::Prefix);
+        assert_eq!(bindings.lookup(&[g, g]), Lookup::Action(Action::First));
+        assert_eq!(bindings.lookup(&[g, chord("j")]), Lookup::Unbound);
+        assert_eq!(bindings.lookup(&[chord("x")]), Lookup::Unbound);
+    }
+
+    #[test]
+    fn overrides_replace_and_remove_defaults() {
+        let bindings = Bindings::emacs().with_overrides(&[
+            ("ctrl-d".parse().unwrap(), Action::Unbound),
+            ("ctrl-x".parse().unwrap(), Action::Hide),
+            ("ctrl-t".parse().unwrap(), Action::Clear),
+        ]);
+        assert_eq!(bindings.lookup(&[chord("ctrl-d")]), Lookup::Unbound);
+        assert_eq!(
+            bindings.lookup(&[chord("ctrl-t")]),
+            Lookup::Action(Action::Clear)
+        );
+        assert_eq!(
+            bindings.keys_for(Action::Hide).unwrap().to_string(),
+            "Ctrl+X"
+        );
+        assert_eq!(bindings.keys_for(Action::CycleMode), None);
+    }
+
+    #[test]
+    fn shows_keys_the_way_the_help_bar_did() {
+        let bindings = Bindings::vi_normal();
+        assert_eq!(bindings.keys_for(Action::First).unwrap().to_string(), "gg");
+        assert_eq!(
+            bindings.keys_for(Action::Mark).unwrap().to_string(),
+            "Space"
+        );
+        let bindings = Bindings::emacs();
+        assert_eq!(bindings.keys_for(Action::Up).unwrap().to_string(), "↑");
+        assert_eq!(
+            bindings.keys_for(Action::PreviewDown).unwrap().to_string(),
+            "S-↓"
+        );
+        assert_eq!(
+            bindings.keys_for(Action::Hide).unwrap().to_string(),
+            "Ctrl+D"
+        );
+    }
+}
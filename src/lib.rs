@@ -0,0 +1,11 @@
+//! History loading and search, shared by the `ih` binary and the benchmarks.
+
+pub mod cache;
+pub mod config;
+pub mod highlight;
+pub mod history;
+pub mod keys;
+pub mod query;
+pub mod search;
+pub mod store;
+pub mod theme;
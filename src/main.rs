@@ -1,18 +1,23 @@
 mod app;
-mod history;
-mod search;
+mod editor;
+mod inline;
 mod ui;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use ihistory::{config, history, search, store, theme};
 use std::path::PathBuf;
 
 const ZSH_SCRIPT: &str = include_str!("../shell/ihistory.zsh");
 const BASH_SCRIPT: &str = include_str!("../shell/ihistory.bash");
+const FISH_SCRIPT: &str = include_str!("../shell/ihistory.fish");
 
 #[derive(Parser, Debug)]
 #[command(name = "ih", bin_name = "ih")]
 #[command(version, about = "A minimal, fast, fuzzy shell history search tool")]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(long, num_args = 0..=1, default_missing_value = "auto")]
     pub init: Option<String>,
 
@@ -20,18 +25,95 @@ pub struct Args {
     #[arg()]
     pub query: Option<String>,
 
-    /// Custom history file path
+    /// Custom history file path (repeat to search several files together)
     #[arg(short, long)]
-    pub file: Option<PathBuf>,
+    pub file: Vec<PathBuf>,
+
+    /// History file format for --file (detected from the contents by default)
+    #[arg(long, value_enum)]
+    pub format: Option<history::HistoryFormat>,
+
+    /// How the query is matched (default: fuzzy, or `mode` in the config)
+    #[arg(long, value_enum)]
+    pub mode: Option<search::SearchMode>,
+
+    /// Letter-case handling (default: insensitive, or `case` in the config)
+    #[arg(long, value_enum)]
+    pub case: Option<search::CaseMode>,
+
+    /// Ignore accents and match full-width characters as their ASCII forms
+    #[arg(long)]
+    pub normalize: bool,
+
+    /// Key style (default: emacs, or `keymap` in the config)
+    #[arg(long, value_enum)]
+    pub keymap: Option<config::Keymap>,
+
+    /// Colour theme (default: dark, or `preset` under `[theme]` in the config)
+    #[arg(long, value_enum)]
+    pub theme: Option<theme::Preset>,
+
+    /// Draw below the prompt instead of full screen, as rows (20) or a share
+    /// of the terminal (40%)
+    #[arg(long)]
+    pub height: Option<config::Height>,
+
+    /// Screen arrangement (default: default, or `layout` in the config)
+    #[arg(long, value_enum)]
+    pub layout: Option<config::Layout>,
 
     /// Max entries to load (0 = unlimited)
     #[arg(short = 'n', long, default_value = "50000")]
     pub limit: usize,
 }
 
+/// Only the shell hooks call these, so they are hidden and named so that no
+/// search query is ever taken for one.
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Append a finished command to ihistory's log
+    #[command(name = "__record", hide = true)]
+    Record(RecordArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RecordArgs {
+    /// Start time, in seconds since the epoch (fractions allowed)
+    #[arg(long)]
+    pub start: Option<f64>,
+
+    /// Run time in milliseconds (defaults to the time elapsed since --start)
+    #[arg(long)]
+    pub duration: Option<u64>,
+
+    /// Exit status
+    #[arg(long)]
+    pub exit: Option<i32>,
+
+    /// Working directory the command ran in (defaults to the current one)
+    #[arg(long)]
+    pub cwd: Option<PathBuf>,
+
+    /// Identifies the shell session
+    #[arg(long)]
+    pub session: Option<String>,
+
+    /// The command line as typed
+    #[arg(allow_hyphen_values = true)]
+    pub command: String,
+}
+
 fn main() {
     let args = Args::parse();
 
+    if let Some(Command::Record(record)) = args.command {
+        if let Err(e) = record_command(record) {
+            eprintln!("ihistory: could not record the command: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     if let Some(ref shell) = args.init {
         match shell.as_str() {
             "zsh" => {
@@ -40,11 +122,14 @@ fn main() {
             "bash" => {
                 print!("{}", BASH_SCRIPT);
             }
+            "fish" => {
+                print!("{}", FISH_SCRIPT);
+            }
             "auto" => {
                 setup_shell();
             }
             other => {
-                eprintln!("Unknown shell: {}. Supported: zsh, bash", other);
+                eprintln!("Unknown shell: {}. Supported: zsh, bash, fish", other);
                 std::process::exit(1);
             }
         }
@@ -60,28 +145,64 @@ fn main() {
     }
 }
 
+fn record_command(args: RecordArgs) -> std::io::Result<()> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default();
+
+    // Hooks report whichever end of the run they can measure cheaply: zsh
+    // and bash know when it started, fish only how long it took.
+    let (start, duration_ms) = match (args.start, args.duration) {
+        (Some(start), Some(duration)) => (start, Some(duration)),
+        (Some(start), None) => (start, Some(((now - start).max(0.0) * 1000.0) as u64)),
+        (None, Some(duration)) => (now - duration as f64 / 1000.0, Some(duration)),
+        (None, None) => (now, None),
+    };
+
+    store::append(&store::Record {
+        start: start as i64,
+        duration_ms,
+        exit_code: args.exit,
+        session: args.session.filter(|s| !s.is_empty()),
+        hostname: Some(gethostname::gethostname().to_string_lossy().into_owned()),
+        cwd: args.cwd.or_else(|| std::env::current_dir().ok()),
+        command: args.command,
+    })
+}
+
 fn setup_shell() {
     // 1. Detect shell from $SHELL
     let shell_env = match std::env::var("SHELL") {
         Ok(s) => s,
         Err(_) => {
             eprintln!("Could not detect shell from $SHELL environment variable.");
-            eprintln!("Run manually: ihistory --init zsh  (or bash)");
+            eprintln!("Run manually: ihistory --init zsh  (or bash, fish)");
             std::process::exit(1);
         }
     };
 
+    let home = dirs::home_dir().unwrap();
     let (shell_name, profile_path) = if shell_env.contains("zsh") {
-        ("zsh", dirs::home_dir().unwrap().join(".zshrc"))
+        ("zsh", home.join(".zshrc"))
     } else if shell_env.contains("bash") {
-        ("bash", dirs::home_dir().unwrap().join(".bashrc"))
+        ("bash", home.join(".bashrc"))
+    } else if shell_env.contains("fish") {
+        ("fish", home.join(".config/fish/config.fish"))
     } else {
         eprintln!("Unsupported shell: {}", shell_env);
-        eprintln!("Supported shells: zsh, bash");
-        eprintln!("Run manually: ihistory --init zsh  (or bash)");
+        eprintln!("Supported shells: zsh, bash, fish");
+        eprintln!("Run manually: ihistory --init zsh  (or bash, fish)");
         std::process::exit(1);
     };
 
+    // fish has no `eval "$(...)"` idiom; it sources the script from a pipe.
+    let init_line = if shell_name == "fish" {
+        "ihistory --init fish | source".to_string()
+    } else {
+        format!("eval \"$(ihistory --init {})\"", shell_name)
+    };
+
     // 2. Check if already configured
     let profile_str = profile_path.display().to_string();
     if let Ok(contents) = std::fs::read_to_string(&profile_path) {
@@ -92,8 +213,11 @@ fn setup_shell() {
         }
     }
 
-    // 3. Append eval line
-    let eval_line = format!("\neval \"$(ihistory --init {})\"\n", shell_name);
+    // 3. Append init line
+    if let Some(parent) = profile_path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let eval_line = format!("\n{}\n", init_line);
     if let Err(e) = std::fs::OpenOptions::new()
         .append(true)
         .create(true)
@@ -106,8 +230,23 @@ fn setup_shell() {
 
     // 4. Print success
     eprintln!("Added to {}:", profile_str);
-    eprintln!("  eval \"$(ihistory --init {})\"", shell_name);
+    eprintln!("  {}", init_line);
     eprintln!();
     eprintln!("Restart your shell or run:");
     eprintln!("  source {}", profile_str);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn searches_for_words_that_were_subcommands() {
+        let args = Args::try_parse_from(["ih", "record"]).unwrap();
+        assert!(args.command.is_none());
+        assert_eq!(args.query.as_deref(), Some("record"));
+
+        let args = Args::try_parse_from(["ih", "__record", "--exit", "1", "ls"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Record(_))));
+    }
+}
@@ -0,0 +1,258 @@
+//! fzf-style extended search syntax.
+//!
+//! | Token      | Matches commands that                      |
+//! | ---------- | ------------------------------------------ |
+//! | `word`     | fuzzy-match `word`                         |
+//! | `'word`    | contain `word` exactly                     |
+//! | `^word`    | start with `word`                          |
+//! | `word$`    | end with `word`                            |
+//! | `^word$`   | are exactly `word`                         |
+//! | `!word`    | do not contain `word` (also `!^w`, `!w$`)  |
+//! | `a \| b`   | match `a` or `b`                           |
+//!
+//! Space-separated terms must all match; `|` binds tighter, so
+//! `^kubectl get | describe` is "starts with kubectl, and fuzzy-matches get
+//! or describe". A backslash escapes a space inside a term.
+//!
+//! The table shows the fuzzy search mode. Other modes change what a plain
+//! `word` means (see `Query::parse`); in substring mode `'word` flips back
+//! to fuzzy, as with fzf's `--exact`.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermKind {
+    Fuzzy,
+    Exact,
+    /// Exact, starting at the beginning of a word.
+    Word,
+    Prefix,
+    Suffix,
+    Equal,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Term {
+    pub kind: TermKind,
+    pub text: String,
+    pub negated: bool,
+}
+
+impl Term {
+    fn parse(token: &str, plain: TermKind) -> Option<Self> {
+        let (negated, rest) = match token.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, token),
+        };
+
+        let (anchored_start, rest) = match rest.strip_prefix('^') {
+            Some(rest) => (true, rest),
+            None => (false, rest),
+        };
+        let (quoted, rest) = match rest.strip_prefix('\'') {
+            Some(rest) if !anchored_start => (true, rest),
+            _ => (false, rest),
+        };
+        let (anchored_end, rest) = match rest.strip_suffix('$') {
+            Some(rest) if !rest.ends_with('\\') => (true, rest),
+            _ => (false, rest),
+        };
+        let text = rest.replace("\\$", "$");
+
+        // A bare operator ("!", "^", "'") is a term still being typed.
+        if text.is_empty() {
+            return None;
+        }
+
+        let kind = match (anchored_start, anchored_end) {
+            (true, true) => TermKind::Equal,
+            (true, false) => TermKind::Prefix,
+            (false, true) => TermKind::Suffix,
+            // Negations are always literal, as in fzf: "not fuzzily
+            // containing" would exclude nearly everything.
+            (false, false) if negated => TermKind::Exact,
+            (false, false) if quoted && plain == TermKind::Exact => TermKind::Fuzzy,
+            (false, false) if quoted => TermKind::Exact,
+            (false, false) => plain,
+        };
+
+        Some(Self {
+            kind,
+            text,
+            negated,
+        })
+    }
+}
+
+/// A parsed query: every group must match, and a group matches when any
+/// of its alternatives does.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    pub groups: Vec<Vec<Term>>,
+}
+
+/// Splits on unescaped whitespace, turning `\ ` into a literal space.
+fn tokenize(input: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|n| n.is_whitespace()) => {
+                current.extend(chars.next());
+            }
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
+
+impl Query {
+    /// Parses the extended syntax, giving unmarked terms the `plain` kind.
+    pub fn parse(input: &str, plain: TermKind) -> Self {
+        let mut groups: Vec<Vec<Term>> = Vec::new();
+        let mut continues_group = false;
+
+        for token in tokenize(input) {
+            if token == "|" {
+                continues_group = !groups.is_empty();
+                continue;
+            }
+            let Some(term) = Term::parse(&token, plain) else {
+                continue;
+            };
+            match groups.last_mut() {
+                Some(group) if continues_group => group.push(term),
+                _ => groups.push(vec![term]),
+            }
+            continues_group = false;
+        }
+
+        Self { groups }
+    }
+
+    /// The whole input, spaces and operators included, as one literal
+    /// prefix.
+    pub fn prefix(input: &str) -> Self {
+        if input.is_empty() {
+            return Self::default();
+        }
+        Self {
+            groups: vec![vec![Term {
+                kind: TermKind::Prefix,
+                text: input.to_string(),
+                negated: false,
+            }]],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(kind: TermKind, text: &str, negated: bool) -> Term {
+        Term {
+            kind,
+            text: text.to_string(),
+            negated,
+        }
+    }
+
+    #[test]
+    fn parses_term_kinds() {
+        let query = Query::parse(
+            "fuzzy 'exact ^prefix suffix$ ^equal$ !neg !^negpre !negsuf$",
+            TermKind::Fuzzy,
+        );
+        let terms: Vec<Term> = query.groups.into_iter().flatten().collect();
+        assert_eq!(
+            terms,
+            [
+                term(TermKind::Fuzzy, "fuzzy", false),
+                term(TermKind::Exact, "exact", false),
+                term(TermKind::Prefix, "prefix", false),
+                term(TermKind::Suffix, "suffix", false),
+                term(TermKind::Equal, "equal", false),
+                term(TermKind::Exact, "neg", true),
+                term(TermKind::Prefix, "negpre", true),
+                term(TermKind::Suffix, "negsuf", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn groups_alternatives() {
+        let query = Query::parse("kubectl get | describe", TermKind::Fuzzy);
+        assert_eq!(query.groups.len(), 2);
+        assert_eq!(query.groups[0], [term(TermKind::Fuzzy, "kubectl", false)]);
+        assert_eq!(
+            query.groups[1],
+            [
+                term(TermKind::Fuzzy, "get", false),
+                term(TermKind::Fuzzy, "describe", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_dangling_operators() {
+        assert!(Query::parse("!", TermKind::Fuzzy).is_empty());
+        assert!(Query::parse("| ^ '", TermKind::Fuzzy).is_empty());
+        assert_eq!(Query::parse("| ls", TermKind::Fuzzy).groups.len(), 1);
+        assert_eq!(Query::parse("ls |", TermKind::Fuzzy).groups.len(), 1);
+    }
+
+    #[test]
+    fn escapes_spaces_and_dollars() {
+        let query = Query::parse("'git\\ push echo\\$", TermKind::Fuzzy);
+        let terms: Vec<Term> = query.groups.into_iter().flatten().collect();
+        assert_eq!(
+            terms,
+            [
+                term(TermKind::Exact, "git push", false),
+                term(TermKind::Fuzzy, "echo$", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn plain_terms_take_the_mode_kind() {
+        let query = Query::parse("git 'push", TermKind::Exact);
+        let terms: Vec<Term> = query.groups.into_iter().flatten().collect();
+        assert_eq!(
+            terms,
+            [
+                term(TermKind::Exact, "git", false),
+                term(TermKind::Fuzzy, "push", false),
+            ]
+        );
+
+        let query = Query::parse("gi pu", TermKind::Word);
+        assert!(query
+            .groups
+            .iter()
+            .flatten()
+            .all(|t| t.kind == TermKind::Word));
+    }
+
+    #[test]
+    fn prefix_keeps_the_whole_input() {
+        let query = Query::prefix("git push !x");
+        assert_eq!(
+            query.groups,
+            [[term(TermKind::Prefix, "git push !x", false)]]
+        );
+        assert!(Query::prefix("").is_empty());
+    }
+}
@@ -1,71 +1,582 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
+use rayon::prelude::*;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use unicode_normalization::UnicodeNormalization;
 
 use crate::history::HistoryEntry;
+use crate::query::{Query, Term, TermKind};
+
+/// Score per matched char for literal (non-fuzzy) terms, roughly what the
+/// fuzzy matcher awards a run of consecutive matches.
+const LITERAL_CHAR_SCORE: i64 = 16;
+
+/// How the query is interpreted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SearchMode {
+    /// Fuzzy terms with the extended syntax described in `query`.
+    #[default]
+    Fuzzy,
+    /// Terms must appear verbatim.
+    Substring,
+    /// The whole query, verbatim, must start the command.
+    Prefix,
+    /// Terms must appear verbatim at the start of a word.
+    WordBoundary,
+    /// The whole query is one regular expression.
+    Regex,
+}
+
+impl SearchMode {
+    pub fn label(self) -> &'static str {
+        match self {
+            SearchMode::Fuzzy => "fuzzy",
+            SearchMode::Substring => "substring",
+            SearchMode::Prefix => "prefix",
+            SearchMode::WordBoundary => "word",
+            SearchMode::Regex => "regex",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            SearchMode::Fuzzy => SearchMode::Substring,
+            SearchMode::Substring => SearchMode::Prefix,
+            SearchMode::Prefix => SearchMode::WordBoundary,
+            SearchMode::WordBoundary => SearchMode::Regex,
+            SearchMode::Regex => SearchMode::Fuzzy,
+        }
+    }
+}
+
+/// Whether letter case matters when matching.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CaseMode {
+    /// Case never matters.
+    #[default]
+    Insensitive,
+    /// Case matters only for a term that contains an uppercase letter.
+    Smart,
+    /// Case always matters.
+    Sensitive,
+}
+
+impl CaseMode {
+    fn is_sensitive(self, pattern: &str) -> bool {
+        match self {
+            CaseMode::Insensitive => false,
+            CaseMode::Smart => pattern.chars().any(char::is_uppercase),
+            CaseMode::Sensitive => true,
+        }
+    }
+
+    /// Like `is_sensitive`, skipping escapes such as `\S` and `\W`, whose
+    /// uppercase letters are regex syntax rather than text to match.
+    fn is_sensitive_regex(self, pattern: &str) -> bool {
+        if self != CaseMode::Smart {
+            return self.is_sensitive(pattern);
+        }
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c == '\\' {
+                chars.next();
+            } else if c.is_uppercase() {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchError {
+    InvalidRegex(String),
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SearchError::InvalidRegex(reason) => write!(f, "Invalid regex: {}", reason),
+        }
+    }
+}
+
+impl From<regex::Error> for SearchError {
+    fn from(e: regex::Error) -> Self {
+        // Syntax errors span several lines, drawing a caret under the
+        // pattern; the last line says what is wrong, which is all that fits
+        // in a title bar.
+        let message = e.to_string();
+        let reason = message
+            .lines()
+            .last()
+            .unwrap_or_default()
+            .trim_start_matches("error: ")
+            .to_string();
+        SearchError::InvalidRegex(reason)
+    }
+}
+
+/// A query compiled for the current mode.
+enum Pattern {
+    Terms(Query),
+    Regex(Regex),
+}
+
+/// Which commands a search considers, by the directory they ran in.
+/// Entries without a recorded directory only show up in `Global`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum SearchScope {
+    #[default]
+    Global,
+    /// Commands run in exactly this directory.
+    Directory(PathBuf),
+    /// Commands run anywhere inside this repository root.
+    Repository(PathBuf),
+}
+
+impl SearchScope {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SearchScope::Global => "global",
+            SearchScope::Directory(_) => "directory",
+            SearchScope::Repository(_) => "repo",
+        }
+    }
+
+    /// Whether any run of the entry happened in scope, not just the
+    /// latest: a command run here yesterday and elsewhere today still
+    /// belongs here.
+    fn contains(&self, entry: &HistoryEntry) -> bool {
+        let runs = entry.occurrences.iter().filter_map(|o| o.cwd.as_ref());
+        let mut dirs = entry.cwd.iter().chain(runs);
+        match self {
+            SearchScope::Global => true,
+            SearchScope::Directory(dir) => dirs.any(|cwd| cwd == dir),
+            SearchScope::Repository(root) => dirs.any(|cwd| cwd.starts_with(root)),
+        }
+    }
+}
+
+/// The closest ancestor of `dir` (itself included) holding a `.git` entry,
+/// which is a directory in a normal checkout and a file in worktrees and
+/// submodules.
+pub fn find_repo_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+        .map(Path::to_path_buf)
+}
 
-#[derive(Debug, Clone)]
+/// A match, referring to the entry by its position in the searched slice.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SearchResult {
-    pub entry: HistoryEntry,
+    pub index: usize,
+    /// Char positions to highlight in the command.
     pub indices: Vec<usize>,
 }
 
+/// How much how often and how recently a command ran counts next to how
+/// well it matches. A match gains
+///
+/// ```text
+/// frequency * ln(runs) + recency * 0.5 ^ (age / half_life_hours)
+/// ```
+///
+/// points on top of its fuzzy score, which is typically 20–200 for short
+/// queries. Set both weights to zero to rank by match quality alone.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ranking {
+    pub frequency: f64,
+    pub recency: f64,
+    pub half_life_hours: f64,
+}
+
+impl Default for Ranking {
+    fn default() -> Self {
+        Self {
+            frequency: 20.0,
+            recency: 40.0,
+            half_life_hours: 7.0 * 24.0,
+        }
+    }
+}
+
+impl Ranking {
+    fn bonus(&self, entry: &HistoryEntry, now: i64) -> i64 {
+        let runs = entry.run_count() as f64;
+        let frequency = self.frequency * runs.ln();
+
+        let recency = match entry.timestamp {
+            Some(ts) if self.half_life_hours > 0.0 => {
+                let age_hours = (now - ts).max(0) as f64 / 3600.0;
+                self.recency * 0.5f64.powf(age_hours / self.half_life_hours)
+            }
+            _ => 0.0,
+        };
+
+        (frequency + recency).round() as i64
+    }
+}
+
 pub struct SearchEngine {
     matcher: SkimMatcherV2,
+    case_matcher: SkimMatcherV2,
+    case: CaseMode,
+    normalize: bool,
+    scope: SearchScope,
+    mode: SearchMode,
+    ranking: Ranking,
+    now: i64,
 }
 
 impl SearchEngine {
     pub fn new() -> Self {
         Self {
             matcher: SkimMatcherV2::default().ignore_case(),
+            case_matcher: SkimMatcherV2::default().respect_case(),
+            case: CaseMode::Insensitive,
+            normalize: false,
+            scope: SearchScope::Global,
+            mode: SearchMode::Fuzzy,
+            ranking: Ranking::default(),
+            now: chrono::Utc::now().timestamp(),
         }
     }
 
-    pub fn search(&self, entries: &[HistoryEntry], query: &str) -> Vec<SearchResult> {
-        if query.is_empty() {
-            return entries
-                .iter()
-                .map(|entry| SearchResult {
-                    entry: entry.clone(),
+    pub fn ranking(mut self, ranking: Ranking) -> Self {
+        self.ranking = ranking;
+        self
+    }
+
+    pub fn case(mut self, case: CaseMode) -> Self {
+        self.case = case;
+        self
+    }
+
+    /// Matches commands and query in compatibility-decomposed form with
+    /// diacritics dropped, so `resume` finds `résumé` and `ｌｓ` finds `ls`.
+    pub fn normalize(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    pub fn scope(&self) -> &SearchScope {
+        &self.scope
+    }
+
+    pub fn set_scope(&mut self, scope: SearchScope) {
+        self.scope = scope;
+    }
+
+    pub fn mode(&self) -> SearchMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: SearchMode) {
+        self.mode = mode;
+    }
+
+    /// `None` when the query places no constraint at all.
+    fn compile(&self, query: &str) -> Result<Option<Pattern>, SearchError> {
+        let parsed = match self.mode {
+            SearchMode::Fuzzy => Query::parse(query, TermKind::Fuzzy),
+            SearchMode::Substring => Query::parse(query, TermKind::Exact),
+            SearchMode::Prefix => Query::prefix(query),
+            SearchMode::WordBoundary => Query::parse(query, TermKind::Word),
+            SearchMode::Regex if query.is_empty() => return Ok(None),
+            SearchMode::Regex => {
+                let regex = RegexBuilder::new(query)
+                    .case_insensitive(!self.case.is_sensitive_regex(query))
+                    .build()?;
+                return Ok(Some(Pattern::Regex(regex)));
+            }
+        };
+        Ok((!parsed.is_empty()).then_some(Pattern::Terms(parsed)))
+    }
+
+    pub fn search(
+        &self,
+        entries: &[HistoryEntry],
+        query: &str,
+    ) -> Result<Vec<SearchResult>, SearchError> {
+        self.search_candidates(entries, (0..entries.len()).into_par_iter(), query)
+    }
+
+    /// Like `search`, but only looks at the entries from `start` on, such
+    /// as those added since an earlier search.
+    pub fn search_from(
+        &self,
+        entries: &[HistoryEntry],
+        start: usize,
+        query: &str,
+    ) -> Result<Vec<SearchResult>, SearchError> {
+        self.search_candidates(entries, (start..entries.len()).into_par_iter(), query)
+    }
+
+    /// Like `search`, but only looks at the entries in `previous`, which
+    /// must be this engine's results for a query that `query` refines.
+    pub fn refine(
+        &self,
+        entries: &[HistoryEntry],
+        previous: &[SearchResult],
+        query: &str,
+    ) -> Result<Vec<SearchResult>, SearchError> {
+        self.search_candidates(entries, previous.par_iter().map(|r| r.index), query)
+    }
+
+    /// Whether every match for `query` also matches `previous`, so that
+    /// `refine` finds the same results as `search`. Appending plain text
+    /// only narrows a query; `|` adds alternatives, `!`, `\` and `$` can
+    /// turn the term being typed into a looser one, and appending to a
+    /// regex can widen it anywhere (`a` → `a|b`). When normalising, the
+    /// query is looked at folded, as full-width `｜` becomes `|`.
+    pub fn refines(&self, previous: &str, query: &str) -> bool {
+        if !query.starts_with(previous) {
+            return false;
+        }
+        let query = if self.normalize {
+            Cow::Owned(fold(query).0)
+        } else {
+            Cow::Borrowed(query)
+        };
+        match self.mode {
+            SearchMode::Regex => false,
+            SearchMode::Prefix => true,
+            _ => !query.contains(['|', '!', '\\', '$']),
+        }
+    }
+
+    fn search_candidates(
+        &self,
+        entries: &[HistoryEntry],
+        candidates: impl ParallelIterator<Item = usize>,
+        query: &str,
+    ) -> Result<Vec<SearchResult>, SearchError> {
+        let candidates = candidates.filter(|&index| self.scope.contains(&entries[index]));
+
+        let query = if self.normalize {
+            Cow::Owned(fold(query).0)
+        } else {
+            Cow::Borrowed(query)
+        };
+        let query = query.as_ref();
+
+        let Some(pattern) = self.compile(query)? else {
+            let mut results: Vec<SearchResult> = candidates
+                .map(|index| SearchResult {
+                    index,
                     indices: Vec::new(),
                 })
                 .collect();
-        }
+            results.par_sort_unstable_by_key(|r| r.index);
+            return Ok(results);
+        };
 
-        let query_lower = query.to_lowercase();
+        let query_sensitive = self.case.is_sensitive(query);
 
-        let mut results: Vec<(i64, SearchResult)> = entries
-            .iter()
-            .filter_map(|entry| {
-                let cmd_lower = entry.command.to_lowercase();
-                let fuzzy_match = self.matcher.fuzzy_indices(&entry.command, query);
-                let has_substring = cmd_lower.contains(&query_lower);
-
-                if fuzzy_match.is_none() && !has_substring {
-                    return None;
-                }
+        let mut results: Vec<(i64, SearchResult)> = candidates
+            .filter_map(|index| {
+                let entry = &entries[index];
+                // ASCII is its own normal form.
+                let folded =
+                    (self.normalize && !entry.command.is_ascii()).then(|| fold(&entry.command));
+                let command = folded.as_ref().map_or(entry.command.as_str(), |f| &f.0);
 
-                let (score, indices) = fuzzy_match.unwrap_or((0, Vec::new()));
+                let (score, indices) = match &pattern {
+                    Pattern::Terms(terms) => self.match_query(terms, command)?,
+                    Pattern::Regex(regex) => match_regex(regex, command)?,
+                };
+                let indices = match &folded {
+                    Some((_, origins)) => unfold_indices(&indices, origins),
+                    None => indices,
+                };
 
-                let score = if cmd_lower.starts_with(&query_lower) {
+                let score = if starts_with(command, query, query_sensitive) {
                     score + 1000
                 } else {
                     score
                 };
+                let score = score + self.ranking.bonus(entry, self.now);
 
-                Some((
-                    score,
-                    SearchResult {
-                        entry: entry.clone(),
-                        indices,
-                    },
-                ))
+                Some((score, SearchResult { index, indices }))
             })
             .collect();
 
-        results.sort_by_key(|b| std::cmp::Reverse(b.0));
+        // Ties keep history order, however the candidates arrived.
+        results.par_sort_unstable_by_key(|(score, r)| (std::cmp::Reverse(*score), r.index));
+
+        Ok(results.into_iter().map(|(_, r)| r).collect())
+    }
+}
+
+impl SearchEngine {
+    /// Scores `command` against every group of the query, merging the
+    /// highlight indices of all matched terms.
+    fn match_query(&self, query: &Query, command: &str) -> Option<(i64, Vec<usize>)> {
+        let chars: Vec<char> = command.chars().collect();
+        let mut total = 0;
+        let mut indices = Vec::new();
+
+        for group in &query.groups {
+            let (score, group_indices) = group
+                .iter()
+                .filter_map(|term| self.match_term(term, command, &chars))
+                .max_by_key(|(score, _)| *score)?;
+            total += score;
+            indices.extend(group_indices);
+        }
+
+        indices.sort_unstable();
+        indices.dedup();
+        Some((total, indices))
+    }
+
+    fn match_term(&self, term: &Term, command: &str, chars: &[char]) -> Option<(i64, Vec<usize>)> {
+        let needle: Vec<char> = term.text.chars().collect();
+        let sensitive = self.case.is_sensitive(&term.text);
+        let matcher = if sensitive {
+            &self.case_matcher
+        } else {
+            &self.matcher
+        };
+        let literal = |start: usize| {
+            (
+                LITERAL_CHAR_SCORE * needle.len() as i64,
+                (start..start + needle.len()).collect::<Vec<_>>(),
+            )
+        };
+
+        let found = match term.kind {
+            TermKind::Fuzzy => matcher.fuzzy_indices(command, &term.text).or_else(|| {
+                find_literal(chars, &needle, sensitive).map(|start| (0, literal(start).1))
+            }),
+            TermKind::Exact => find_literal(chars, &needle, sensitive).map(literal),
+            TermKind::Word => find_word(chars, &needle, sensitive).map(literal),
+            TermKind::Prefix => chars_match_at(chars, &needle, 0, sensitive).then(|| literal(0)),
+            TermKind::Suffix => chars
+                .len()
+                .checked_sub(needle.len())
+                .filter(|&start| chars_match_at(chars, &needle, start, sensitive))
+                .map(literal),
+            TermKind::Equal => (chars.len() == needle.len()
+                && chars_match_at(chars, &needle, 0, sensitive))
+            .then(|| literal(0)),
+        };
+
+        if term.negated {
+            return match found {
+                Some(_) => None,
+                None => Some((0, Vec::new())),
+            };
+        }
+        found
+    }
+}
+
+/// Regex matches carry no quality score of their own; ranking among them is
+/// left to frecency. Match spans come back as byte ranges and are turned
+/// into the char indices the highlighter expects.
+fn match_regex(regex: &Regex, command: &str) -> Option<(i64, Vec<usize>)> {
+    let mut spans = regex.find_iter(command).peekable();
+    spans.peek()?;
+
+    let mut indices = Vec::new();
+    let mut span = spans.next();
+    for (char_index, (byte_index, _)) in command.char_indices().enumerate() {
+        while span.is_some_and(|m| byte_index >= m.end()) {
+            span = spans.next();
+        }
+        let Some(m) = span else {
+            break;
+        };
+        if byte_index >= m.start() {
+            indices.push(char_index);
+        }
+    }
+    Some((0, indices))
+}
+
+/// Combining marks that only add an accent to the letter before them.
+fn is_diacritic(c: char) -> bool {
+    matches!(c,
+        '\u{0300}'..='\u{036F}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}'
+        | '\u{FE20}'..='\u{FE2F}')
+}
+
+/// NFKD with diacritics dropped, along with the index of the original char
+/// each folded char came from. Chars are decomposed one at a time so that
+/// every folded char has exactly one origin.
+fn fold(text: &str) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut origins = Vec::with_capacity(text.len());
+    for (index, c) in text.chars().enumerate() {
+        for d in std::iter::once(c).nfkd().filter(|&d| !is_diacritic(d)) {
+            folded.push(d);
+            origins.push(index);
+        }
+    }
+    (folded, origins)
+}
+
+/// Maps highlight indices into a folded command back onto its original
+/// chars. A char that folded into several (`ﬁ` → `fi`) is highlighted
+/// once.
+fn unfold_indices(indices: &[usize], origins: &[usize]) -> Vec<usize> {
+    let mut unfolded: Vec<usize> = indices.iter().map(|&i| origins[i]).collect();
+    unfolded.dedup();
+    unfolded
+}
+
+fn chars_eq(a: char, b: char, sensitive: bool) -> bool {
+    a == b || (!sensitive && a.to_lowercase().eq(b.to_lowercase()))
+}
+
+fn starts_with(command: &str, prefix: &str, sensitive: bool) -> bool {
+    let mut chars = command.chars();
+    prefix
+        .chars()
+        .all(|p| chars.next().is_some_and(|c| chars_eq(c, p, sensitive)))
+}
+
+fn chars_match_at(haystack: &[char], needle: &[char], start: usize, sensitive: bool) -> bool {
+    haystack
+        .get(start..start + needle.len())
+        .is_some_and(|window| {
+            window
+                .iter()
+                .zip(needle)
+                .all(|(&a, &b)| chars_eq(a, b, sensitive))
+        })
+}
 
-        results.into_iter().map(|(_, r)| r).collect()
+/// Char index of the first occurrence of `needle`.
+fn find_literal(haystack: &[char], needle: &[char], sensitive: bool) -> Option<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None;
     }
+    (0..=haystack.len() - needle.len())
+        .find(|&start| chars_match_at(haystack, needle, start, sensitive))
+}
+
+/// Char index of the first occurrence of `needle` that starts a word.
+fn find_word(haystack: &[char], needle: &[char], sensitive: bool) -> Option<usize> {
+    if needle.is_empty() || needle.len() > haystack.len() {
+        return None;
+    }
+    (0..=haystack.len() - needle.len()).find(|&start| {
+        let at_boundary = start == 0 || !haystack[start - 1].is_alphanumeric();
+        at_boundary && chars_match_at(haystack, needle, start, sensitive)
+    })
 }
 
 impl Default for SearchEngine {
@@ -77,12 +588,12 @@ impl Default for SearchEngine {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::history::Occurrence;
 
     fn make_entry(cmd: &str) -> HistoryEntry {
         HistoryEntry {
             command: cmd.to_string(),
-            timestamp: None,
-            raw_line: None,
+            ..Default::default()
         }
     }
 
@@ -94,7 +605,7 @@ mod tests {
     fn test_empty_query_returns_all() {
         let engine = SearchEngine::new();
         let entries = make_entries(&["git status", "ls -la", "cd ~"]);
-        let results = engine.search(&entries, "");
+        let results = engine.search(&entries, "").unwrap();
         assert_eq!(results.len(), 3);
     }
 
@@ -102,16 +613,16 @@ mod tests {
     fn test_fuzzy_match() {
         let engine = SearchEngine::new();
         let entries = make_entries(&["git commit -m 'test'", "git push", "ls -la"]);
-        let results = engine.search(&entries, "gco");
+        let results = engine.search(&entries, "gco").unwrap();
         assert!(!results.is_empty());
-        assert!(results[0].entry.command.contains("commit"));
+        assert!(entries[results[0].index].command.contains("commit"));
     }
 
     #[test]
     fn test_case_insensitive() {
         let engine = SearchEngine::new();
         let entries = make_entries(&["Git Status", "git push"]);
-        let results = engine.search(&entries, "GIT");
+        let results = engine.search(&entries, "GIT").unwrap();
         assert_eq!(results.len(), 2);
     }
 
@@ -119,7 +630,7 @@ mod tests {
     fn test_no_match() {
         let engine = SearchEngine::new();
         let entries = make_entries(&["git status", "ls -la"]);
-        let results = engine.search(&entries, "xyz123");
+        let results = engine.search(&entries, "xyz123").unwrap();
         assert!(results.is_empty());
     }
 
@@ -127,8 +638,430 @@ mod tests {
     fn test_substring_match() {
         let engine = SearchEngine::new();
         let entries = make_entries(&["vim config.local.yaml", "ls -la"]);
-        let results = engine.search(&entries, "local");
+        let results = engine.search(&entries, "local").unwrap();
         assert_eq!(results.len(), 1);
-        assert!(results[0].entry.command.contains("local"));
+        assert!(entries[results[0].index].command.contains("local"));
+    }
+
+    fn make_entry_in(cmd: &str, cwd: &str) -> HistoryEntry {
+        HistoryEntry {
+            command: cmd.to_string(),
+            cwd: Some(PathBuf::from(cwd)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_directory_scope() {
+        let mut engine = SearchEngine::new();
+        let entries = vec![
+            make_entry_in("cargo build", "/src/app"),
+            make_entry_in("cargo test", "/src/app/crates/core"),
+            make_entry_in("cargo run", "/tmp"),
+            make_entry("cargo fmt"),
+        ];
+
+        engine.set_scope(SearchScope::Directory(PathBuf::from("/src/app")));
+        let results = engine.search(&entries, "cargo").unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(entries[results[0].index].command, "cargo build");
+
+        engine.set_scope(SearchScope::Repository(PathBuf::from("/src/app")));
+        let results = engine.search(&entries, "").unwrap();
+        assert_eq!(commands(&entries, &results), ["cargo build", "cargo test"]);
+
+        engine.set_scope(SearchScope::Global);
+        assert_eq!(engine.search(&entries, "cargo").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_scope_finds_commands_by_any_run() {
+        use crate::history::History;
+
+        let mut history = History::default();
+        let run_in = |dir: &str, timestamp| HistoryEntry {
+            timestamp: Some(timestamp),
+            ..make_entry_in("make", dir)
+        };
+        history.extend([run_in("/src/b", 200), run_in("/src/a/lib", 100)]);
+        let entries = history.entries();
+
+        let mut engine = SearchEngine::new();
+        engine.set_scope(SearchScope::Repository(PathBuf::from("/src/a")));
+        assert_eq!(engine.search(entries, "make").unwrap().len(), 1);
+        engine.set_scope(SearchScope::Directory(PathBuf::from("/src/b")));
+        assert_eq!(engine.search(entries, "make").unwrap().len(), 1);
+        engine.set_scope(SearchScope::Directory(PathBuf::from("/src/c")));
+        assert!(engine.search(entries, "make").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_repository_scope_matches_whole_components() {
+        let mut engine = SearchEngine::new();
+        let entries = vec![make_entry_in("make", "/src/app-old")];
+        engine.set_scope(SearchScope::Repository(PathBuf::from("/src/app")));
+        assert!(engine.search(&entries, "").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_repo_root() {
+        let dir = std::env::temp_dir().join(format!("ihistory-test-repo-{}", std::process::id()));
+        let nested = dir.join("src/deep");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+
+        let root = find_repo_root(&nested);
+        std::fs::remove_dir_all(&dir).ok();
+        assert_eq!(root, Some(dir));
+    }
+
+    #[test]
+    fn test_frecency_beats_slightly_better_match() {
+        let now = 1_700_000_000;
+        let mut engine = SearchEngine::new();
+        engine.now = now;
+        let entries = vec![
+            HistoryEntry {
+                command: "cargo publish".to_string(),
+                timestamp: Some(now - 3600),
+                occurrences: vec![Occurrence::default(); 40],
+                ..Default::default()
+            },
+            HistoryEntry {
+                command: "git push".to_string(),
+                timestamp: Some(now - 365 * 24 * 3600),
+                ..Default::default()
+            },
+        ];
+
+        // On match quality alone, the one-off command wins: it has the
+        // query in one piece.
+        let mut plain = SearchEngine::new().ranking(Ranking {
+            frequency: 0.0,
+            recency: 0.0,
+            ..Ranking::default()
+        });
+        plain.now = now;
+        let results = plain.search(&entries, "push").unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(entries[results[0].index].command, "git push");
+
+        let results = engine.search(&entries, "push").unwrap();
+        assert_eq!(entries[results[0].index].command, "cargo publish");
+    }
+
+    #[test]
+    fn test_ranking_bonus_decays_with_age() {
+        let ranking = Ranking::default();
+        let now = 1_700_000_000;
+        let entry = |age: i64| HistoryEntry {
+            timestamp: Some(now - age),
+            ..Default::default()
+        };
+        let fresh = ranking.bonus(&entry(0), now);
+        let week = ranking.bonus(&entry(7 * 24 * 3600), now);
+        assert_eq!(fresh, 40);
+        assert_eq!(week, 20);
+        assert_eq!(ranking.bonus(&HistoryEntry::default(), now), 0);
+    }
+
+    fn commands<'a>(entries: &'a [HistoryEntry], results: &[SearchResult]) -> Vec<&'a str> {
+        results
+            .iter()
+            .map(|r| entries[r.index].command.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_and_terms() {
+        let engine = SearchEngine::new();
+        let entries = make_entries(&["kubectl get pods", "kubectl logs web", "git log"]);
+        let results = engine.search(&entries, "kubectl log").unwrap();
+        assert_eq!(commands(&entries, &results), ["kubectl logs web"]);
+    }
+
+    #[test]
+    fn test_negation() {
+        let engine = SearchEngine::new();
+        let entries = make_entries(&["kubectl get pods", "kubectl describe pod web", "ls"]);
+        let results = engine.search(&entries, "kubectl !get").unwrap();
+        assert_eq!(commands(&entries, &results), ["kubectl describe pod web"]);
+    }
+
+    #[test]
+    fn test_exact_prefix_suffix() {
+        let engine = SearchEngine::new();
+        let entries = make_entries(&["git status", "cargo test --all", "echo git"]);
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "^git").unwrap()),
+            ["git status"]
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "git$").unwrap()),
+            ["echo git"]
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "'stat").unwrap()),
+            ["git status"]
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "'test").unwrap()),
+            ["cargo test --all"]
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "^echo\\ git$").unwrap()),
+            ["echo git"]
+        );
+    }
+
+    #[test]
+    fn test_alternation() {
+        let engine = SearchEngine::new();
+        let entries = make_entries(&["make build", "cargo build", "npm run build", "ls"]);
+        let results = engine.search(&entries, "build ^make | ^cargo").unwrap();
+        let mut found = commands(&entries, &results);
+        found.sort();
+        assert_eq!(found, ["cargo build", "make build"]);
+    }
+
+    #[test]
+    fn test_merges_indices_from_every_term() {
+        let engine = SearchEngine::new();
+        let entries = make_entries(&["git push origin"]);
+        let results = engine.search(&entries, "^git 'origin").unwrap();
+        assert_eq!(results[0].indices, [0, 1, 2, 9, 10, 11, 12, 13, 14]);
+    }
+
+    #[test]
+    fn test_regex_mode() {
+        let mut engine = SearchEngine::new();
+        engine.set_mode(SearchMode::Regex);
+        let entries = make_entries(&["git push origin main", "git pull", "ls"]);
+        let results = engine.search(&entries, "^git p(ush|ull)$").unwrap();
+        assert_eq!(commands(&entries, &results), ["git pull"]);
+        assert_eq!(results[0].indices, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_regex_indices_are_char_positions() {
+        let mut engine = SearchEngine::new();
+        engine.set_mode(SearchMode::Regex);
+        let entries = make_entries(&["écho déjà vu"]);
+        let results = engine.search(&entries, "d.j").unwrap();
+        assert_eq!(results[0].indices, [5, 6, 7]);
+    }
+
+    #[test]
+    fn test_invalid_regex_is_an_error() {
+        let mut engine = SearchEngine::new();
+        engine.set_mode(SearchMode::Regex);
+        let entries = make_entries(&["ls"]);
+        let err = engine.search(&entries, "foo(").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid regex: unclosed group");
+    }
+
+    #[test]
+    fn test_substring_mode() {
+        let mut engine = SearchEngine::new();
+        engine.set_mode(SearchMode::Substring);
+        let entries = make_entries(&["git commit", "grep -c foo"]);
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "gco").unwrap()),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "mit").unwrap()),
+            ["git commit"]
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "'gcm").unwrap()),
+            ["git commit"]
+        );
+    }
+
+    #[test]
+    fn test_prefix_mode() {
+        let mut engine = SearchEngine::new();
+        engine.set_mode(SearchMode::Prefix);
+        let entries = make_entries(&["git push origin", "echo git push"]);
+        let results = engine.search(&entries, "git pu").unwrap();
+        assert_eq!(commands(&entries, &results), ["git push origin"]);
+        assert_eq!(results[0].indices, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_word_boundary_mode() {
+        let mut engine = SearchEngine::new();
+        engine.set_mode(SearchMode::WordBoundary);
+        let entries = make_entries(&["git push", "legit pushd", "cd /src/push-tool"]);
+        let results = engine.search(&entries, "gi pu").unwrap();
+        assert_eq!(commands(&entries, &results), ["git push"]);
+        let results = engine.search(&entries, "push").unwrap();
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn test_mode_cycle_visits_every_mode() {
+        let mut mode = SearchMode::Fuzzy;
+        let mut seen = Vec::new();
+        for _ in 0..5 {
+            seen.push(mode);
+            mode = mode.next();
+        }
+        assert_eq!(mode, SearchMode::Fuzzy);
+        assert_eq!(seen.len(), 5);
+        assert!(seen.contains(&SearchMode::WordBoundary));
+    }
+
+    #[test]
+    fn test_smart_case() {
+        let engine = SearchEngine::new().case(CaseMode::Smart);
+        let entries = make_entries(&["Makefile", "make build"]);
+        assert_eq!(engine.search(&entries, "make").unwrap().len(), 2);
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "Make").unwrap()),
+            ["Makefile"]
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "'Make").unwrap()),
+            ["Makefile"]
+        );
+    }
+
+    #[test]
+    fn test_smart_case_is_per_term() {
+        let engine = SearchEngine::new().case(CaseMode::Smart);
+        let entries = make_entries(&["git commit -m WIP", "GIT commit -m wip"]);
+        let results = engine.search(&entries, "git WIP").unwrap();
+        assert_eq!(commands(&entries, &results), ["git commit -m WIP"]);
+    }
+
+    #[test]
+    fn test_case_sensitive() {
+        let engine = SearchEngine::new().case(CaseMode::Sensitive);
+        let entries = make_entries(&["Makefile", "make build"]);
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "make").unwrap()),
+            ["make build"]
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "^make").unwrap()),
+            ["make build"]
+        );
+    }
+
+    #[test]
+    fn test_smart_case_regex_ignores_escapes() {
+        let mut engine = SearchEngine::new().case(CaseMode::Smart);
+        engine.set_mode(SearchMode::Regex);
+        let entries = make_entries(&["echo HELLO", "echo hello"]);
+        assert_eq!(engine.search(&entries, "hel\\S+").unwrap().len(), 2);
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "HEL\\S+").unwrap()),
+            ["echo HELLO"]
+        );
+    }
+
+    #[test]
+    fn test_prefix_bonus_respects_case() {
+        let engine = SearchEngine::new()
+            .case(CaseMode::Sensitive)
+            .ranking(Ranking {
+                frequency: 0.0,
+                recency: 0.0,
+                ..Ranking::default()
+            });
+        let entries = make_entries(&["xgit", "git"]);
+        let results = engine.search(&entries, "git").unwrap();
+        assert_eq!(commands(&entries, &results)[0], "git");
+    }
+
+    #[test]
+    fn test_results_keep_history_order_on_ties() {
+        let engine = SearchEngine::new();
+        let entries = make_entries(&["ls -a", "ls -b", "ls -c"]);
+        let results = engine.search(&entries, "'ls").unwrap();
+        assert_eq!(commands(&entries, &results), ["ls -a", "ls -b", "ls -c"]);
+    }
+
+    #[test]
+    fn test_refines() {
+        let mut engine = SearchEngine::new();
+        assert!(engine.refines("", "git"));
+        assert!(engine.refines("git", "git push"));
+        assert!(engine.refines("^gi", "^git 'ori"));
+        assert!(!engine.refines("git push", "git"));
+        assert!(!engine.refines("git", "git | hg"));
+        assert!(!engine.refines("git", "git !push"));
+        assert!(!engine.refines("!pu", "!push"));
+        assert!(!engine.refines("echo", "echo$"));
+        assert!(!engine.refines("echo", "echo\\ x"));
+
+        // Full-width operators only count once folded.
+        assert!(engine.refines("git", "git ｜ hg"));
+        let normalizing = SearchEngine::new().normalize(true);
+        assert!(!normalizing.refines("git", "git ｜ hg"));
+        assert!(!normalizing.refines("echo", "echo＄"));
+        assert!(normalizing.refines("caf", "café"));
+
+        engine.set_mode(SearchMode::Prefix);
+        assert!(engine.refines("git |", "git | x"));
+        engine.set_mode(SearchMode::Regex);
+        assert!(!engine.refines("a", "ab"));
+    }
+
+    #[test]
+    fn test_refine_matches_full_search() {
+        let engine = SearchEngine::new();
+        let entries = make_entries(&[
+            "git push origin main",
+            "git pull",
+            "gitk --all",
+            "grep -r pattern .",
+            "cargo build",
+            "git push --force",
+        ]);
+        let mut previous = engine.search(&entries, "").unwrap();
+        let mut typed = String::new();
+        for c in "git pu".chars() {
+            typed.push(c);
+            assert!(engine.refines(&typed[..typed.len() - 1], &typed));
+            let refined = engine.refine(&entries, &previous, &typed).unwrap();
+            assert_eq!(refined, engine.search(&entries, &typed).unwrap());
+            previous = refined;
+        }
+        assert_eq!(previous.len(), 3);
+    }
+
+    #[test]
+    fn test_normalize_folds_accents_and_width() {
+        let engine = SearchEngine::new().normalize(true);
+        let entries = make_entries(&["cp résumé.pdf ~", "ｌｓ －ｌａ", "ls"]);
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "'resume").unwrap()),
+            ["cp résumé.pdf ~"]
+        );
+        assert_eq!(
+            commands(&entries, &engine.search(&entries, "ＲÉSUMÉ").unwrap()),
+            ["cp résumé.pdf ~"]
+        );
+        assert_eq!(engine.search(&entries, "^ls").unwrap().len(), 2);
+
+        let plain = SearchEngine::new();
+        assert!(plain.search(&entries, "'resume").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_normalize_highlights_original_chars() {
+        let engine = SearchEngine::new().normalize(true);
+        // "e" plus a combining acute accent, then a ligature.
+        let entries = make_entries(&["cafe\u{301} ﬁx"]);
+        let results = engine.search(&entries, "'cafe 'fix").unwrap();
+        assert_eq!(results[0].indices, [0, 1, 2, 3, 6, 7]);
+
+        let mut engine = SearchEngine::new().normalize(true);
+        engine.set_mode(SearchMode::Regex);
+        let results = engine.search(&entries, "e ﬁ").unwrap();
+        assert_eq!(results[0].indices, [3, 5, 6]);
     }
 }
@@ -0,0 +1,203 @@
+//! ihistory's own history log, written by the shell hooks through
+//! `ihistory __record`. Unlike shell history files it keeps where and how each
+//! command ran.
+//!
+//! The log is append-only text, one command per line:
+//!
+//! ```text
+//! START<TAB>DURATION_MS<TAB>EXIT<TAB>SESSION<TAB>HOSTNAME<TAB>CWD<TAB>COMMAND
+//! ```
+//!
+//! Backslashes, tabs and newlines inside fields are escaped as `\\`, `\t`
+//! and `\n`, so a record never spans lines and fields never contain the
+//! separator. Unknown values are left empty. A single short `write` per
+//! record keeps concurrent appends from several shells intact.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::config;
+use crate::history::HistoryEntry;
+
+const FIELDS: usize = 7;
+
+/// One finished command, as reported by a shell hook.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    pub start: i64,
+    pub duration_ms: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub session: Option<String>,
+    pub hostname: Option<String>,
+    pub cwd: Option<PathBuf>,
+    pub command: String,
+}
+
+pub fn store_path() -> Option<PathBuf> {
+    Some(config::config_dir()?.join("history.log"))
+}
+
+fn escape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn optional(field: &str) -> Option<String> {
+    (!field.is_empty()).then(|| unescape(field))
+}
+
+impl Record {
+    fn to_line(&self) -> String {
+        let cwd = self.cwd.as_ref().map(|p| p.to_string_lossy());
+        let fields = [
+            self.start.to_string(),
+            self.duration_ms.map(|d| d.to_string()).unwrap_or_default(),
+            self.exit_code.map(|c| c.to_string()).unwrap_or_default(),
+            escape(self.session.as_deref().unwrap_or_default()),
+            escape(self.hostname.as_deref().unwrap_or_default()),
+            escape(cwd.as_deref().unwrap_or_default()),
+            escape(&self.command),
+        ];
+        let mut line = fields.join("\t");
+        line.push('\n');
+        line
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != FIELDS {
+            return None;
+        }
+        let command = unescape(fields[6]);
+        if command.trim().is_empty() {
+            return None;
+        }
+        Some(Self {
+            start: fields[0].parse().ok()?,
+            duration_ms: fields[1].parse().ok(),
+            exit_code: fields[2].parse().ok(),
+            session: optional(fields[3]),
+            hostname: optional(fields[4]),
+            cwd: optional(fields[5]).map(PathBuf::from),
+            command,
+        })
+    }
+
+    fn into_entry(self, raw_line: &str) -> HistoryEntry {
+        HistoryEntry {
+            command: self.command,
+            timestamp: Some(self.start),
+            raw_line: Some(raw_line.to_string()),
+            cwd: self.cwd,
+            exit_code: self.exit_code,
+            duration_ms: self.duration_ms,
+            hostname: self.hostname,
+            session: self.session,
+            logged: true,
+            ..Default::default()
+        }
+    }
+}
+
+/// True for lines shaped like a record; used by format detection.
+pub fn is_record_line(line: &str) -> bool {
+    Record::from_line(line).is_some()
+}
+
+pub fn append(record: &Record) -> io::Result<()> {
+    let path = store_path().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "Could not find config directory")
+    })?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(record.to_line().as_bytes())
+}
+
+pub fn parse_store(content: &[u8]) -> Vec<HistoryEntry> {
+    String::from_utf8_lossy(content)
+        .lines()
+        .filter_map(|line| Record::from_line(line).map(|r| r.into_entry(line)))
+        .collect()
+}
+
+/// Returns the log's path if the shell hooks have written to it.
+pub fn existing_store() -> Option<PathBuf> {
+    store_path().filter(|path| fs::metadata(path).is_ok_and(|m| m.len() > 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_separators() {
+        let field = "a\tb\nc\\d";
+        assert_eq!(escape(field), "a\\tb\\nc\\\\d");
+        assert_eq!(unescape(&escape(field)), field);
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let record = Record {
+            start: 1706500000,
+            duration_ms: Some(1520),
+            exit_code: Some(2),
+            session: Some("4242.17".to_string()),
+            hostname: Some("laptop".to_string()),
+            cwd: Some(PathBuf::from("/home/me/src/app")),
+            command: "for f in *; do\n\techo \"$f\"\ndone".to_string(),
+        };
+        let line = record.to_line();
+        assert_eq!(line.matches('\n').count(), 1);
+        assert_eq!(Record::from_line(line.trim_end_matches('\n')), Some(record));
+    }
+
+    #[test]
+    fn unknown_fields_stay_empty() {
+        let record = Record {
+            start: 1706500000,
+            command: "ls".to_string(),
+            ..Default::default()
+        };
+        let line = record.to_line();
+        assert_eq!(line, "1706500000\t\t\t\t\t\tls\n");
+        assert_eq!(Record::from_line(line.trim_end()), Some(record));
+    }
+
+    #[test]
+    fn parse_store_skips_malformed_lines() {
+        let content = b"1706500000\t12\t0\ts\th\t/tmp\tls -la\ngarbage\n\n";
+        let entries = parse_store(content);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "ls -la");
+        assert_eq!(entries[0].timestamp, Some(1706500000));
+        assert_eq!(entries[0].exit_code, Some(0));
+        assert_eq!(entries[0].cwd, Some(PathBuf::from("/tmp")));
+    }
+}
@@ -0,0 +1,437 @@
+//! Colours, as named presets the config can pick from and adjust.
+//!
+//! A theme is a handful of styles, one per role on screen. Presets are
+//! written in whatever colours suit them and brought down to what the
+//! terminal can show when the theme is built; with `NO_COLOR` set, every
+//! role is told apart by modifiers alone.
+
+use ratatui::style::{Color, Modifier, Style};
+use serde::{Deserialize, Deserializer};
+
+/// The built-in themes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// Bright text on a dark background.
+    #[default]
+    Dark,
+    /// Dark text on a light background.
+    Light,
+    /// Ethan Schoonover's Solarized palette (dark).
+    Solarized,
+    /// Bold, saturated colours and a solid selection bar.
+    HighContrast,
+    /// No colours; bold, dim, underline and reverse video only.
+    Monochrome,
+}
+
+impl Preset {
+    /// The selection bar on a 16-colour terminal. The nearest basic colour
+    /// to a subtle background is usually the background itself, which
+    /// would leave only bold to show the selected row.
+    fn basic_selection(self) -> Option<Color> {
+        match self {
+            Preset::Dark | Preset::Solarized => Some(Color::DarkGray),
+            Preset::Light => Some(Color::Gray),
+            Preset::HighContrast | Preset::Monochrome => None,
+        }
+    }
+}
+
+/// A `[theme]` table: a preset, with any of its colours replaced.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// `--theme` overrides it.
+    pub preset: Preset,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub accent: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub muted: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub text: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub matched: Option<Color>,
+    /// The background of the selected row.
+    #[serde(deserialize_with = "deserialize_color")]
+    pub selected: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub error: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub flag: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub string: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub variable: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub operator: Option<Color>,
+}
+
+/// A colour name (`"yellow"`, `"light-blue"`), a 256-colour index
+/// (`"208"`) or `"#rrggbb"`.
+fn deserialize_color<'de, D>(deserializer: D) -> Result<Option<Color>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    name.parse()
+        .map(Some)
+        .map_err(|_| serde::de::Error::custom(format!("unknown colour `{}`", name)))
+}
+
+/// How many colours the terminal can show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ColorDepth {
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+impl ColorDepth {
+    /// Going by `$COLORTERM` and `$TERM`, which is all terminals offer.
+    /// Anything unrecognised gets the 16 colours every terminal has.
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+}
+
+/// `NO_COLOR` set to anything but the empty string, per no-color.org.
+pub fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// The style for each role on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// The prompt, the input box and the keys in the help bar.
+    pub accent: Style,
+    /// Borders, timestamps and descriptions.
+    pub muted: Style,
+    /// Commands.
+    pub text: Style,
+    /// The parts of a command the query matched.
+    pub matched: Style,
+    /// The selected row.
+    pub selected: Style,
+    /// The status line when something went wrong, and failed exit codes.
+    pub error: Style,
+    /// Options in the preview's command: `-v`, `--force`.
+    pub flag: Style,
+    /// Quoted text in the preview's command.
+    pub string: Style,
+    /// `$HOME`, `$(…)` and assignments in the preview's command.
+    pub variable: Style,
+    /// Pipes, `&&`, `;` and redirections in the preview's command.
+    pub operator: Style,
+}
+
+impl Theme {
+    pub fn preset(preset: Preset) -> Self {
+        let fg = |color| Style::default().fg(color);
+        let bold = Modifier::BOLD;
+        match preset {
+            Preset::Dark => Self {
+                accent: fg(Color::Cyan),
+                muted: fg(Color::DarkGray),
+                text: fg(Color::White),
+                matched: fg(Color::Yellow).add_modifier(bold),
+                selected: Style::default()
+                    .bg(Color::Rgb(40, 44, 52))
+                    .add_modifier(bold),
+                error: fg(Color::Red),
+                flag: fg(Color::LightBlue),
+                string: fg(Color::Green),
+                variable: fg(Color::LightMagenta),
+                operator: fg(Color::Cyan).add_modifier(bold),
+            },
+            Preset::Light => Self {
+                accent: fg(Color::Rgb(0, 95, 175)),
+                muted: fg(Color::Rgb(128, 128, 128)),
+                text: fg(Color::Rgb(28, 28, 28)),
+                matched: fg(Color::Rgb(175, 0, 135)).add_modifier(bold),
+                selected: Style::default()
+                    .bg(Color::Rgb(218, 226, 236))
+                    .add_modifier(bold),
+                error: fg(Color::Rgb(215, 0, 0)),
+                flag: fg(Color::Rgb(0, 135, 135)),
+                string: fg(Color::Rgb(0, 135, 0)),
+                variable: fg(Color::Rgb(135, 0, 175)),
+                operator: fg(Color::Rgb(175, 95, 0)).add_modifier(bold),
+            },
+            Preset::Solarized => Self {
+                accent: fg(Color::Rgb(38, 139, 210)),
+                muted: fg(Color::Rgb(88, 110, 117)),
+                text: fg(Color::Rgb(147, 161, 161)),
+                matched: fg(Color::Rgb(181, 137, 0)).add_modifier(bold),
+                selected: Style::default()
+                    .bg(Color::Rgb(7, 54, 66))
+                    .add_modifier(bold),
+                error: fg(Color::Rgb(220, 50, 47)),
+                flag: fg(Color::Rgb(42, 161, 152)),
+                string: fg(Color::Rgb(133, 153, 0)),
+                variable: fg(Color::Rgb(108, 113, 196)),
+                operator: fg(Color::Rgb(203, 75, 22)).add_modifier(bold),
+            },
+            Preset::HighContrast => Self {
+                accent: fg(Color::LightCyan).add_modifier(bold),
+                muted: fg(Color::Gray),
+                text: fg(Color::White),
+                matched: fg(Color::LightYellow).add_modifier(bold | Modifier::UNDERLINED),
+                selected: Style::default()
+                    .fg(Color::White)
+                    .bg(Color::Blue)
+                    .add_modifier(bold),
+                error: fg(Color::LightRed).add_modifier(bold),
+                flag: fg(Color::LightBlue),
+                string: fg(Color::LightGreen),
+                variable: fg(Color::LightMagenta),
+                operator: fg(Color::LightCyan).add_modifier(bold),
+            },
+            Preset::Monochrome => Self {
+                accent: Style::default().add_modifier(bold),
+                muted: Style::default().add_modifier(Modifier::DIM),
+                text: Style::default(),
+                matched: Style::default().add_modifier(bold | Modifier::UNDERLINED),
+                selected: Style::default().add_modifier(Modifier::REVERSED),
+                error: Style::default().add_modifier(bold),
+                flag: Style::default(),
+                string: Style::default().add_modifier(Modifier::ITALIC),
+                variable: Style::default().add_modifier(Modifier::UNDERLINED),
+                operator: Style::default().add_modifier(bold),
+            },
+        }
+    }
+
+    /// The theme `config` describes, as `depth` allows. `no_color` wins
+    /// over everything else.
+    pub fn from_config(
+        config: &ThemeConfig,
+        preset: Option<Preset>,
+        depth: ColorDepth,
+        no_color: bool,
+    ) -> Self {
+        if no_color {
+            return Self::preset(Preset::Monochrome);
+        }
+
+        let preset = preset.unwrap_or(config.preset);
+        let mut theme = Self::preset(preset);
+        for (style, color) in [
+            (&mut theme.accent, config.accent),
+            (&mut theme.muted, config.muted),
+            (&mut theme.text, config.text),
+            (&mut theme.matched, config.matched),
+            (&mut theme.error, config.error),
+            (&mut theme.flag, config.flag),
+            (&mut theme.string, config.string),
+            (&mut theme.variable, config.variable),
+            (&mut theme.operator, config.operator),
+        ] {
+            if let Some(color) = color {
+                *style = style.fg(color);
+            }
+        }
+        if let Some(color) = config.selected {
+            theme.selected = theme.selected.bg(color);
+        } else if depth == ColorDepth::Ansi16 {
+            if let Some(color) = preset.basic_selection() {
+                theme.selected = theme.selected.bg(color);
+            }
+        }
+
+        for style in [
+            &mut theme.accent,
+            &mut theme.muted,
+            &mut theme.text,
+            &mut theme.matched,
+            &mut theme.selected,
+            &mut theme.error,
+            &mut theme.flag,
+            &mut theme.string,
+            &mut theme.variable,
+            &mut theme.operator,
+        ] {
+            style.fg = style.fg.map(|color| fit(color, depth));
+            style.bg = style.bg.map(|color| fit(color, depth));
+        }
+        theme
+    }
+
+    /// The mode label in the help bar: the accent colour as a background.
+    pub fn badge(&self) -> Style {
+        self.accent
+            .add_modifier(Modifier::REVERSED | Modifier::BOLD)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::preset(Preset::Dark)
+    }
+}
+
+/// xterm's default RGB values for the 16 basic colours.
+const ANSI: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Channel values of the 6×6×6 cube in the 256-colour palette.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The nearest colour `depth` can show. The 16 basic colours are left
+/// alone: every terminal has them, in whatever shades its user picked.
+fn fit(color: Color, depth: ColorDepth) -> Color {
+    let rgb = match color {
+        Color::Rgb(r, g, b) if depth < ColorDepth::TrueColor => (r, g, b),
+        Color::Indexed(i) if depth < ColorDepth::Ansi256 => indexed_rgb(i),
+        _ => return color,
+    };
+    match depth {
+        ColorDepth::Ansi256 => Color::Indexed(nearest_indexed(rgb)),
+        _ => nearest(ANSI.iter().copied(), rgb),
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    [(r1, r2), (g1, g2), (b1, b2)]
+        .iter()
+        .map(|&(a, b)| (a as i32 - b as i32).pow(2) as u32)
+        .sum()
+}
+
+fn nearest<T>(candidates: impl Iterator<Item = (T, (u8, u8, u8))>, rgb: (u8, u8, u8)) -> T {
+    candidates
+        .min_by_key(|&(_, candidate)| distance(candidate, rgb))
+        .map(|(value, _)| value)
+        .expect("no candidates")
+}
+
+/// The closest of the cube and grey-ramp colours (16–255); the first 16
+/// are skipped as their shades vary between terminals.
+fn nearest_indexed(rgb: (u8, u8, u8)) -> u8 {
+    nearest((16..=255).map(|i| (i, indexed_rgb(i))), rgb)
+}
+
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..=15 => ANSI[index as usize].1,
+        16..=231 => {
+            let i = (index - 16) as usize;
+            (CUBE[i / 36], CUBE[i / 6 % 6], CUBE[i % 6])
+        }
+        _ => {
+            let level = 8 + (index - 232) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_colours_the_terminal_can_show() {
+        let rgb = Color::Rgb(40, 44, 52);
+        assert_eq!(fit(rgb, ColorDepth::TrueColor), rgb);
+        assert_eq!(
+            fit(Color::Indexed(208), ColorDepth::Ansi256),
+            Color::Indexed(208)
+        );
+        assert_eq!(fit(Color::Cyan, ColorDepth::Ansi16), Color::Cyan);
+    }
+
+    #[test]
+    fn falls_back_to_the_nearest_palette_colour() {
+        assert_eq!(
+            fit(Color::Rgb(255, 0, 0), ColorDepth::Ansi256),
+            Color::Indexed(196)
+        );
+        assert_eq!(
+            fit(Color::Rgb(40, 44, 52), ColorDepth::Ansi256),
+            Color::Indexed(236)
+        );
+        assert_eq!(fit(Color::Rgb(220, 50, 47), ColorDepth::Ansi16), Color::Red);
+        assert_eq!(fit(Color::Indexed(231), ColorDepth::Ansi16), Color::White);
+
+        // The nearest basic colour to the dark selection bar is black, the
+        // terminal's own background; the preset picks a visible one.
+        assert_eq!(
+            fit(Color::Rgb(40, 44, 52), ColorDepth::Ansi16),
+            Color::Black
+        );
+        let config = ThemeConfig::default();
+        let theme = Theme::from_config(&config, None, ColorDepth::Ansi16, false);
+        assert_eq!(theme.selected.bg, Some(Color::DarkGray));
+        let theme = Theme::from_config(&config, Some(Preset::Light), ColorDepth::Ansi16, false);
+        assert_eq!(theme.selected.bg, Some(Color::Gray));
+    }
+
+    #[test]
+    fn no_color_leaves_only_modifiers() {
+        let config = ThemeConfig {
+            accent: Some(Color::Magenta),
+            ..Default::default()
+        };
+        let theme = Theme::from_config(&config, None, ColorDepth::TrueColor, true);
+        for style in [
+            theme.accent,
+            theme.muted,
+            theme.text,
+            theme.matched,
+            theme.selected,
+            theme.error,
+            theme.flag,
+            theme.string,
+            theme.variable,
+            theme.operator,
+        ] {
+            assert_eq!((style.fg, style.bg), (None, None));
+        }
+        assert_ne!(theme.matched, theme.text);
+        assert_ne!(theme.selected, theme.text);
+    }
+
+    #[test]
+    fn overrides_preset_colours() {
+        let config = ThemeConfig {
+            preset: Preset::Light,
+            matched: Some(Color::Rgb(255, 135, 0)),
+            selected: Some(Color::Indexed(254)),
+            ..Default::default()
+        };
+        let theme = Theme::from_config(&config, None, ColorDepth::Ansi256, false);
+        assert_eq!(theme.matched.fg, Some(Color::Indexed(208)));
+        assert!(theme.matched.add_modifier.contains(Modifier::BOLD));
+        assert_eq!(theme.selected.bg, Some(Color::Indexed(254)));
+        assert_eq!(theme.text.fg, Some(Color::Indexed(234)));
+
+        // The flag picks the preset; the colours still apply.
+        let theme = Theme::from_config(&config, Some(Preset::Dark), ColorDepth::TrueColor, false);
+        assert_eq!(theme.accent, Theme::preset(Preset::Dark).accent);
+        assert_eq!(theme.matched.fg, Some(Color::Rgb(255, 135, 0)));
+    }
+}
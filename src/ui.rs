@@ -1,26 +1,52 @@
+use std::path::Path;
+
 use chrono::{DateTime, Local, Utc};
 use ratatui::{
-    layout::{Constraint, Direction, Layout, Rect},
-    style::{Color, Modifier, Style},
+    layout::{Constraint, Direction, Layout, Position, Rect},
+    style::{Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    widgets::{Block, Borders, List, ListDirection, ListItem, ListState, Paragraph},
     Frame,
 };
 
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-use crate::search::SearchResult;
+use crate::app::ViMode;
+use crate::editor::LineEditor;
+use ihistory::config;
+use ihistory::highlight::{highlight, Token};
+use ihistory::history::HistoryEntry;
+use ihistory::keys::{Action, Bindings};
+use ihistory::search::SearchResult;
+use ihistory::theme::Theme;
 
 const INPUT_HEIGHT: u16 = 3;
 const PREVIEW_HEIGHT: u16 = 8;
-const PREVIEW_LINES: usize = 6;
 const HELP_HEIGHT: u16 = 1;
+/// Three results and the borders around them.
+const MIN_RESULTS_HEIGHT: u16 = 5;
+/// Narrower than this, `preview-right` stacks the preview like `default`.
+const PREVIEW_RIGHT_MIN_WIDTH: u16 = 100;
+const PREVIEW_RIGHT_PERCENT: u16 = 45;
+const PROMPT: &str = "> ";
+/// Stands in for line breaks in the result list.
+const NEWLINE: char = '↵';
 
-const COLOR_ACCENT: Color = Color::Cyan;
-const COLOR_MUTED: Color = Color::DarkGray;
-const COLOR_TEXT: Color = Color::White;
-const COLOR_MATCH: Color = Color::Yellow;
-const COLOR_SELECTED_BG: Color = Color::Rgb(40, 44, 52);
+/// The help bar, in order. An entry shows the first key bound to each of
+/// its actions and is left out when none are bound.
+const HELP: &[(&[Action], &str)] = &[
+    (&[Action::Down, Action::Up], "navigate"),
+    (&[Action::First, Action::Last], "top/bottom"),
+    (&[Action::Accept], "select"),
+    (&[Action::Execute], "run"),
+    (&[Action::Mark], "mark"),
+    (&[Action::Hide], "hide"),
+    (&[Action::CycleMode], "mode"),
+    (&[Action::CycleScope], "scope"),
+    (&[Action::NormalMode], "normal mode"),
+    (&[Action::InsertMode], "search"),
+    (&[Action::Quit], "cancel"),
+];
 
 fn format_relative_time(timestamp: Option<i64>, now: i64) -> Option<String> {
     let ts = timestamp?;
@@ -62,102 +88,331 @@ fn format_relative_time(timestamp: Option<i64>, now: i64) -> Option<String> {
     })
 }
 
-pub struct UI;
+/// "run 37 times, first 2024-03-02, last 5m ago", leaving out whatever the
+/// history file did not record.
+fn format_run_summary(entry: &HistoryEntry, now: i64) -> Option<String> {
+    let mut parts = Vec::new();
+
+    let runs = entry.run_count();
+    if runs > 1 {
+        parts.push(format!("run {} times", runs));
+        let first = entry
+            .first_seen()
+            .filter(|&first| Some(first) != entry.timestamp)
+            .and_then(|first| DateTime::from_timestamp(first, 0));
+        if let Some(first) = first {
+            let local: DateTime<Local> = first.into();
+            parts.push(format!("first {}", local.format("%Y-%m-%d")));
+        }
+    } else {
+        parts.push("run once".to_string());
+    }
+
+    if let Some(last) = format_relative_time(entry.timestamp, now) {
+        parts.push(format!("last {}", last));
+    }
+
+    if runs == 1 && parts.len() == 1 {
+        return None;
+    }
+    Some(parts.join(", "))
+}
+
+/// The command split into screen rows of `width` columns, breaking at its
+/// own newlines too, with each piece of shell syntax in its style.
+fn highlighted_lines(theme: &Theme, command: &str, width: usize) -> Vec<Line<'static>> {
+    if width == 0 {
+        return Vec::new();
+    }
+
+    let mut lines = Vec::new();
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut row_width = 0;
+    let mut run = String::new();
+    let mut run_style = Style::default();
+
+    for (token, range) in highlight(command) {
+        let style = match token {
+            Token::Command => theme.text.add_modifier(Modifier::BOLD),
+            Token::Flag => theme.flag,
+            Token::String => theme.string,
+            Token::Variable => theme.variable,
+            Token::Operator => theme.operator,
+            Token::Comment => theme.muted,
+            Token::Plain => theme.text,
+        };
+        for c in command[range].chars() {
+            let c = if c == '\t' { ' ' } else { c };
+            let c_width = c.width().unwrap_or(0);
+            if c == '\n' || row_width + c_width > width {
+                if !run.is_empty() {
+                    spans.push(Span::styled(std::mem::take(&mut run), run_style));
+                }
+                lines.push(Line::from(std::mem::take(&mut spans)));
+                row_width = 0;
+                if c == '\n' {
+                    continue;
+                }
+            }
+            if style != run_style && !run.is_empty() {
+                spans.push(Span::styled(std::mem::take(&mut run), run_style));
+            }
+            run_style = style;
+            run.push(c);
+            row_width += c_width;
+        }
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, run_style));
+    }
+    lines.push(Line::from(spans));
+    lines
+}
+
+/// When the command last ran, how often, where and how it ended, as far as
+/// the history recorded it.
+fn entry_details(theme: &Theme, entry: &HistoryEntry, now: i64) -> Vec<Span<'static>> {
+    let mut details = Vec::new();
+    if let Some(last) = entry
+        .timestamp
+        .and_then(|ts| DateTime::from_timestamp(ts, 0))
+    {
+        let local: DateTime<Local> = last.into();
+        details.push(Span::styled(
+            local.format("%Y-%m-%d %H:%M").to_string(),
+            theme.muted,
+        ));
+    }
+    if let Some(summary) = format_run_summary(entry, now) {
+        details.push(Span::styled(summary, theme.muted));
+    }
+    if let Some(cwd) = &entry.cwd {
+        let home = dirs::home_dir();
+        details.push(Span::styled(display_dir(cwd, home.as_deref()), theme.muted));
+    }
+    match entry.exit_code {
+        Some(0) => details.push(Span::styled("exit 0", theme.muted)),
+        Some(code) => details.push(Span::styled(format!("exit {}", code), theme.error)),
+        None => {}
+    }
+    details
+}
+
+/// `path` with the home directory written as `~`.
+fn display_dir(path: &Path, home: Option<&Path>) -> String {
+    match home.and_then(|home| path.strip_prefix(home).ok()) {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_string(),
+        Some(rest) => format!("~/{}", rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Lays `items` out in rows of `width` columns, `separator` between those
+/// sharing a row. An item too long for any row gets one to itself.
+fn pack(items: Vec<Span<'static>>, separator: Span<'static>, width: usize) -> Vec<Line<'static>> {
+    let mut lines = Vec::new();
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut row_width = 0;
+    for item in items {
+        let item_width = item.width();
+        if !spans.is_empty() && row_width + separator.width() + item_width > width {
+            lines.push(Line::from(std::mem::take(&mut spans)));
+            row_width = 0;
+        }
+        if !spans.is_empty() {
+            spans.push(separator.clone());
+            row_width += separator.width();
+        }
+        spans.push(item);
+        row_width += item_width;
+    }
+    if !spans.is_empty() {
+        lines.push(Line::from(spans));
+    }
+    lines
+}
+
+pub struct UI {
+    show_sources: bool,
+    theme: Theme,
+    layout: config::Layout,
+}
 
 impl UI {
     pub fn new() -> Self {
-        Self
+        Self {
+            show_sources: false,
+            theme: Theme::default(),
+            layout: config::Layout::Default,
+        }
+    }
+
+    pub fn layout(mut self, layout: config::Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Whether results run up the screen from the best one, which sits
+    /// just above the query.
+    pub fn lists_upwards(&self) -> bool {
+        self.layout == config::Layout::Reverse
+    }
+
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = theme;
+        self
+    }
+
+    /// Tags each result with the history file it came from. Only worth the
+    /// columns when several files are loaded.
+    pub fn show_sources(mut self, show: bool) -> Self {
+        self.show_sources = show;
+        self
     }
 
     #[allow(clippy::too_many_arguments)]
     pub fn render(
         &self,
         frame: &mut Frame,
-        query: &str,
+        query: &LineEditor,
+        mode: &str,
+        entries: &[HistoryEntry],
         results: &[SearchResult],
+        marked: &[usize],
+        vi_mode: Option<ViMode>,
+        bindings: &Bindings,
+        scope: &str,
+        loading: bool,
         selected_index: usize,
         scroll_offset: usize,
         list_state: &mut ListState,
+        preview_scroll: &mut usize,
         status_message: Option<&str>,
     ) -> usize {
-        let selected_command = results.get(selected_index).map(|r| &r.entry.command);
+        let selected_entry = results.get(selected_index).map(|r| &entries[r.index]);
 
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Length(INPUT_HEIGHT),
-                Constraint::Min(1),
-                Constraint::Length(PREVIEW_HEIGHT),
-                Constraint::Length(HELP_HEIGHT),
-            ])
-            .split(frame.area());
+        let areas = layout(frame.area(), self.layout);
 
-        self.render_input(frame, chunks[0], query);
+        self.render_input(frame, areas.input, query, mode);
         let new_offset = self.render_results(
             frame,
-            chunks[1],
+            areas.results,
+            entries,
             results,
+            marked,
+            scope,
+            loading,
             selected_index,
             scroll_offset,
             list_state,
             status_message,
         );
 
-        if let Some(cmd) = selected_command {
-            self.render_preview(frame, chunks[2], cmd);
-        } else {
-            self.render_empty_preview(frame, chunks[2]);
+        match (areas.preview, selected_entry) {
+            (Some(area), Some(entry)) => {
+                self.render_preview(frame, area, entry, bindings, preview_scroll)
+            }
+            (Some(area), None) => self.render_empty_preview(frame, area),
+            (None, _) => {}
         }
-        self.render_help_bar(frame, chunks[3]);
+        self.render_help_bar(frame, areas.help, vi_mode, bindings);
 
         new_offset
     }
 
-    fn render_preview(&self, frame: &mut Frame, area: Rect, command: &str) {
-        let inner_width = area.width.saturating_sub(2) as usize;
-        let wrapped = textwrap::wrap(command, inner_width);
-        let lines: Vec<Line> = wrapped
-            .iter()
-            .take(PREVIEW_LINES)
-            .map(|s| Line::from(Span::styled(s.to_string(), Style::default().fg(COLOR_TEXT))))
-            .collect();
+    /// The command, highlighted and scrolled by `scroll` rows, over what is
+    /// known about its runs. `scroll` is kept within the command.
+    fn render_preview(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        entry: &HistoryEntry,
+        bindings: &Bindings,
+        scroll: &mut usize,
+    ) {
+        let width = area.width.saturating_sub(2) as usize;
+        let height = area.height.saturating_sub(2) as usize;
 
-        let preview = Paragraph::new(lines).block(
-            Block::default()
-                .borders(Borders::ALL)
-                .border_style(Style::default().fg(COLOR_MUTED))
-                .title(" Preview "),
+        // The details stay at the foot, leaving the command a row at least.
+        let details = pack(
+            entry_details(&self.theme, entry, Utc::now().timestamp()),
+            Span::styled(" · ", self.theme.muted),
+            width,
         );
+        let details_rows = details.len().min(height.saturating_sub(1));
+        let command_rows = height - details_rows;
 
-        frame.render_widget(preview, area);
+        let command = highlighted_lines(&self.theme, &entry.command, width);
+        let total = command.len();
+        *scroll = (*scroll).min(total.saturating_sub(command_rows));
+
+        let mut lines: Vec<Line> = command
+            .into_iter()
+            .skip(*scroll)
+            .take(command_rows)
+            .collect();
+        lines.resize(command_rows, Line::default());
+        lines.extend(details.into_iter().take(details_rows));
+
+        let mut block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(self.theme.muted)
+            .title(" Preview ");
+        if total > command_rows {
+            let shown = format!(
+                "{}–{} of {}",
+                *scroll + 1,
+                (*scroll + command_rows).min(total),
+                total
+            );
+            let keys: Vec<String> = [Action::PreviewUp, Action::PreviewDown]
+                .iter()
+                .filter_map(|&action| bindings.keys_for(action))
+                .map(|keys| keys.to_string())
+                .collect();
+            let position = if keys.is_empty() {
+                format!(" {} ", shown)
+            } else {
+                format!(" {} · {} ", shown, keys.join("/"))
+            };
+            block = block.title_bottom(Line::from(position).right_aligned());
+        }
+
+        frame.render_widget(Paragraph::new(lines).block(block), area);
     }
 
     fn render_empty_preview(&self, frame: &mut Frame, area: Rect) {
         let preview = Paragraph::new("").block(
             Block::default()
                 .borders(Borders::ALL)
-                .border_style(Style::default().fg(COLOR_MUTED))
+                .border_style(self.theme.muted)
                 .title(" Preview "),
         );
 
         frame.render_widget(preview, area);
     }
 
-    fn render_input(&self, frame: &mut Frame, area: Rect, query: &str) {
+    fn render_input(&self, frame: &mut Frame, area: Rect, query: &LineEditor, mode: &str) {
+        // Inside the borders, after the prompt, with a column spare for the
+        // cursor at the very end.
+        let width = (area.width as usize).saturating_sub(2 + PROMPT.width() + 1);
+        let (skip, skipped_width) = input_scroll(query.text(), query.cursor_width(), width);
+
         let input_text = Line::from(vec![
-            Span::styled("> ", Style::default().fg(COLOR_ACCENT)),
-            Span::raw(query),
-            Span::styled("_", Style::default().fg(Color::Gray)),
+            Span::styled(PROMPT, self.theme.accent),
+            Span::raw(&query.text()[skip..]),
         ]);
 
         let input = Paragraph::new(input_text).block(
             Block::default()
                 .borders(Borders::ALL)
-                .border_style(Style::default().fg(COLOR_ACCENT))
-                .title(" ihistory "),
+                .border_style(self.theme.accent)
+                .title(format!(" ihistory · {} ", mode)),
         );
 
         frame.render_widget(input, area);
+        let cursor_x = 1 + PROMPT.width() + query.cursor_width() - skipped_width;
+        frame.set_cursor_position(Position::new(area.x + cursor_x as u16, area.y + 1));
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -165,7 +420,11 @@ impl UI {
         &self,
         frame: &mut Frame,
         area: Rect,
+        entries: &[HistoryEntry],
         results: &[SearchResult],
+        marked: &[usize],
+        scope: &str,
+        loading: bool,
         selected_index: usize,
         scroll_offset: usize,
         list_state: &mut ListState,
@@ -192,20 +451,27 @@ impl UI {
             .map(|(i, result)| {
                 let actual_index = start + i;
                 let is_selected = actual_index == selected_index;
+                let entry = &entries[result.index];
 
+                let source = if self.show_sources {
+                    entry.source.as_deref()
+                } else {
+                    None
+                };
                 let line = render_command_line(
-                    &result.entry.command,
+                    &self.theme,
+                    &entry.command,
                     &result.indices,
-                    result.entry.timestamp,
+                    entry.timestamp,
+                    source,
                     is_selected,
+                    marked.contains(&result.index),
                     available_width,
                     now,
                 );
 
                 let style = if is_selected {
-                    Style::default()
-                        .bg(COLOR_SELECTED_BG)
-                        .add_modifier(Modifier::BOLD)
+                    self.theme.selected
                 } else {
                     Style::default()
                 };
@@ -215,15 +481,25 @@ impl UI {
             .collect();
 
         let (title, border_style) = if let Some(msg) = status_message {
-            (format!(" {} ", msg), Style::default().fg(Color::Red))
+            (format!(" {} ", msg), self.theme.error)
         } else {
-            (
-                format!(" {} results ", results.len()),
-                Style::default().fg(COLOR_MUTED),
-            )
+            let mut parts = vec![format!("{} results", results.len())];
+            if !marked.is_empty() {
+                parts.push(format!("{} marked", marked.len()));
+            }
+            parts.push(scope.to_string());
+            if loading {
+                parts.push("loading…".to_string());
+            }
+            (format!(" {} ", parts.join(" · ")), self.theme.muted)
         };
 
-        let list = List::new(items).block(
+        let direction = if self.lists_upwards() {
+            ListDirection::BottomToTop
+        } else {
+            ListDirection::TopToBottom
+        };
+        let list = List::new(items).direction(direction).block(
             Block::default()
                 .borders(Borders::ALL)
                 .border_style(border_style)
@@ -235,44 +511,157 @@ impl UI {
         new_offset
     }
 
-    fn render_help_bar(&self, frame: &mut Frame, area: Rect) {
-        let help = Paragraph::new(Line::from(vec![
-            Span::styled("↑↓", Style::default().fg(COLOR_ACCENT)),
-            Span::styled(" navigate  ", Style::default().fg(COLOR_MUTED)),
-            Span::styled("Enter", Style::default().fg(COLOR_ACCENT)),
-            Span::styled(" select  ", Style::default().fg(COLOR_MUTED)),
-            Span::styled("Tab", Style::default().fg(COLOR_ACCENT)),
-            Span::styled(" run  ", Style::default().fg(COLOR_MUTED)),
-            Span::styled("Ctrl+D", Style::default().fg(COLOR_ACCENT)),
-            Span::styled(" hide  ", Style::default().fg(COLOR_MUTED)),
-            Span::styled("Esc", Style::default().fg(COLOR_ACCENT)),
-            Span::styled(" cancel", Style::default().fg(COLOR_MUTED)),
-        ]));
+    /// The keys that matter right now. Under the vi keymap the bar leads
+    /// with the current mode, the way vi's own status line does.
+    fn render_help_bar(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        vi_mode: Option<ViMode>,
+        bindings: &Bindings,
+    ) {
+        let mode = match vi_mode {
+            None => None,
+            Some(ViMode::Insert) => Some("INSERT"),
+            Some(ViMode::Normal) => Some("NORMAL"),
+        };
+        let keys = help_keys(bindings);
+
+        let mut spans = Vec::with_capacity(keys.len() * 2 + 2);
+        if let Some(mode) = mode {
+            spans.push(Span::styled(format!(" {} ", mode), self.theme.badge()));
+            spans.push(Span::raw(" "));
+        }
+        for (i, (key, action)) in keys.iter().enumerate() {
+            let separator = if i + 1 < keys.len() { "  " } else { "" };
+            spans.push(Span::styled(key.clone(), self.theme.accent));
+            spans.push(Span::styled(
+                format!(" {}{}", action, separator),
+                self.theme.muted,
+            ));
+        }
+
+        frame.render_widget(Paragraph::new(Line::from(spans)), area);
+    }
+}
+
+/// Where each part of the screen goes.
+struct Areas {
+    input: Rect,
+    results: Rect,
+    /// Left out when the screen is short, before results get cramped.
+    preview: Option<Rect>,
+    help: Rect,
+}
+
+fn layout(area: Rect, arrangement: config::Layout) -> Areas {
+    if arrangement == config::Layout::PreviewRight && area.width >= PREVIEW_RIGHT_MIN_WIDTH {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(INPUT_HEIGHT),
+                Constraint::Min(1),
+                Constraint::Length(HELP_HEIGHT),
+            ])
+            .split(area);
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Min(1),
+                Constraint::Percentage(PREVIEW_RIGHT_PERCENT),
+            ])
+            .split(rows[1]);
+        return Areas {
+            input: rows[0],
+            results: columns[0],
+            preview: Some(columns[1]),
+            help: rows[2],
+        };
+    }
+
+    let with_preview =
+        area.height >= INPUT_HEIGHT + MIN_RESULTS_HEIGHT + PREVIEW_HEIGHT + HELP_HEIGHT;
+    let input = Constraint::Length(INPUT_HEIGHT);
+    let results = Constraint::Min(1);
+    let preview = Constraint::Length(if with_preview { PREVIEW_HEIGHT } else { 0 });
+    let help = Constraint::Length(HELP_HEIGHT);
+    // Indices of the input, results and preview in each arrangement; the
+    // help bar always comes last.
+    let (constraints, [i, r, p]) = match arrangement {
+        config::Layout::Reverse => ([preview, results, input, help], [2, 1, 0]),
+        config::Layout::InputBottom => ([results, preview, input, help], [2, 0, 1]),
+        config::Layout::Default | config::Layout::PreviewRight => {
+            ([input, results, preview, help], [0, 1, 2])
+        }
+    };
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(constraints)
+        .split(area);
+    Areas {
+        input: chunks[i],
+        results: chunks[r],
+        preview: with_preview.then_some(chunks[p]),
+        help: chunks[3],
+    }
+}
 
-        frame.render_widget(help, area);
+/// The help bar's entries for `bindings`: keys and what they do.
+fn help_keys(bindings: &Bindings) -> Vec<(String, &'static str)> {
+    HELP.iter()
+        .filter_map(|(actions, label)| {
+            let keys: Vec<String> = actions
+                .iter()
+                .filter_map(|&action| bindings.keys_for(action))
+                .map(|keys| keys.to_string())
+                .collect();
+            (!keys.is_empty()).then(|| (keys.join("/"), *label))
+        })
+        .collect()
+}
+
+/// Where the visible part of the query starts, as a byte offset and the
+/// columns skipped, so that the cursor stays within `width` columns. The
+/// query scrolls only once the text before the cursor outgrows the box.
+fn input_scroll(text: &str, cursor_width: usize, width: usize) -> (usize, usize) {
+    let mut skip = 0;
+    let mut skipped_width = 0;
+    for c in text.chars() {
+        if cursor_width - skipped_width <= width {
+            break;
+        }
+        skip += c.len_utf8();
+        skipped_width += c.width().unwrap_or(0);
     }
+    (skip, skipped_width)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn render_command_line(
+    theme: &Theme,
     command: &str,
     match_indices: &[usize],
     timestamp: Option<i64>,
+    source: Option<&str>,
     is_selected: bool,
+    is_marked: bool,
     available_width: usize,
     now: i64,
 ) -> Line<'static> {
-    let prefix_style = Style::default().fg(if is_selected {
-        COLOR_ACCENT
+    let prefix_style = if is_selected || is_marked {
+        theme.accent
     } else {
-        COLOR_MUTED
-    });
-    let normal_style = Style::default().fg(COLOR_TEXT);
-    let match_style = Style::default()
-        .fg(COLOR_MATCH)
-        .add_modifier(Modifier::BOLD);
-    let time_style = Style::default().fg(COLOR_MUTED);
-
-    let time_str = format_relative_time(timestamp, now);
+        theme.muted
+    };
+    let normal_style = theme.text;
+    let match_style = theme.matched;
+    let time_style = theme.muted;
+
+    let time_str = match (source, format_relative_time(timestamp, now)) {
+        (Some(source), Some(time)) => Some(format!("{}  {}", source, time)),
+        (Some(source), None) => Some(source.to_string()),
+        (None, time) => time,
+    };
     let time_width = time_str.as_ref().map(|s| s.width() + 2).unwrap_or(0);
     let prefix_width = 2;
     let max_cmd_width = available_width.saturating_sub(prefix_width + time_width);
@@ -281,49 +670,58 @@ fn render_command_line(
     // slicing panics inside multi-byte characters, and column math has to
     // count double-width glyphs as two cells. The matcher hands out char
     // indices, so highlighting walks chars as well.
+    //
+    // A multi-line command stays on its row: each newline shows as `↵` and
+    // the indentation after it is dropped. Dropped chars still count
+    // towards the indices, which refer to the command as stored.
     let budget = max_cmd_width.saturating_sub(3);
     let matches: std::collections::HashSet<usize> = match_indices.iter().copied().collect();
-    let mut kept: Vec<(char, bool)> = Vec::new();
+    let mut kept: Vec<(char, Style)> = Vec::new();
     let mut used_width = 0;
     let mut needs_truncation = false;
+    let mut line_start = false;
     for (i, ch) in command.chars().enumerate() {
-        let w = ch.width().unwrap_or(0);
+        let (shown, style) = match ch {
+            '\n' => (NEWLINE, theme.muted),
+            ' ' | '\t' if line_start => continue,
+            '\t' => (' ', normal_style),
+            _ => (ch, normal_style),
+        };
+        line_start = ch == '\n';
+        let style = if matches.contains(&i) {
+            match_style
+        } else {
+            style
+        };
+        let w = shown.width().unwrap_or(0);
         if used_width + w > budget {
             needs_truncation = true;
             break;
         }
         used_width += w;
-        kept.push((ch, matches.contains(&i)));
+        kept.push((shown, style));
     }
 
     let mut spans = Vec::with_capacity(8);
-    spans.push(Span::styled(
-        if is_selected { "> " } else { "  " },
-        prefix_style,
-    ));
+    let prefix = match (is_marked, is_selected) {
+        (true, _) => "● ",
+        (false, true) => "> ",
+        (false, false) => "  ",
+    };
+    spans.push(Span::styled(prefix, prefix_style));
 
     // Consecutive chars with the same styling collapse into one span.
     let mut run = String::new();
-    let mut run_matched = false;
-    for &(ch, matched) in &kept {
-        if matched != run_matched && !run.is_empty() {
-            let style = if run_matched {
-                match_style
-            } else {
-                normal_style
-            };
-            spans.push(Span::styled(std::mem::take(&mut run), style));
+    let mut run_style = normal_style;
+    for &(ch, style) in &kept {
+        if style != run_style && !run.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut run), run_style));
         }
-        run_matched = matched;
+        run_style = style;
         run.push(ch);
     }
     if !run.is_empty() {
-        let style = if run_matched {
-            match_style
-        } else {
-            normal_style
-        };
-        spans.push(Span::styled(run, style));
+        spans.push(Span::styled(run, run_style));
     }
 
     if needs_truncation {
@@ -359,7 +757,7 @@ mod tests {
     fn matched_text(line: &Line) -> String {
         line.spans
             .iter()
-            .filter(|s| s.style.fg == Some(COLOR_MATCH))
+            .filter(|s| s.style == Theme::default().matched)
             .map(|s| s.content.as_ref())
             .collect()
     }
@@ -368,19 +766,64 @@ mod tests {
     fn truncates_non_ascii_without_panicking() {
         // Byte-based truncation used to slice inside 'ö' and panic.
         let cmd = "gít cömmit -m 'ünïcöde chängé' --amend --no-verify";
-        let line = render_command_line(cmd, &[], None, false, 24, 0);
+        let line =
+            render_command_line(&Theme::default(), cmd, &[], None, None, false, false, 24, 0);
         let text = text_of(&line);
         assert!(text.contains("..."));
         assert!(text.starts_with("  gít cömmit"));
     }
 
+    #[test]
+    fn keeps_multi_line_commands_on_one_row() {
+        let cmd = "for f in *; do\n    echo $f\ndone";
+        // "do" of "done", after the dropped indentation.
+        let indices = [27, 28];
+        let line = render_command_line(
+            &Theme::default(),
+            cmd,
+            &indices,
+            None,
+            None,
+            false,
+            false,
+            80,
+            0,
+        );
+        assert_eq!(text_of(&line), "  for f in *; do↵echo $f↵done");
+        assert_eq!(matched_text(&line), "do");
+
+        // The glyph takes a column like any other char when truncating.
+        let line = render_command_line(
+            &Theme::default(),
+            cmd,
+            &[],
+            None,
+            None,
+            false,
+            false,
+            2 + 15 + 3,
+            0,
+        );
+        assert_eq!(text_of(&line), "  for f in *; do↵...");
+    }
+
     #[test]
     fn highlights_char_indices_not_bytes() {
         // "é" is two bytes; a byte-based highlighter would mark the wrong
         // columns for every index after it.
         let cmd = "économie status";
         let indices = [0, 9, 10]; // é, s, t (char positions)
-        let line = render_command_line(cmd, &indices, None, false, 80, 0);
+        let line = render_command_line(
+            &Theme::default(),
+            cmd,
+            &indices,
+            None,
+            None,
+            false,
+            false,
+            80,
+            0,
+        );
         assert_eq!(matched_text(&line), "ést");
     }
 
@@ -389,7 +832,8 @@ mod tests {
         // Four CJK chars occupy eight cells; a char-counting truncation
         // would overrun the column budget.
         let cmd = "echo 日本語検索 && ls";
-        let line = render_command_line(cmd, &[], None, false, 16, 0);
+        let line =
+            render_command_line(&Theme::default(), cmd, &[], None, None, false, false, 16, 0);
         let text = text_of(&line);
         assert!(text.contains("..."));
         let width: usize = text.width();
@@ -399,14 +843,438 @@ mod tests {
     #[test]
     fn pads_the_timestamp_by_display_width() {
         let now = 1_700_000_000;
-        let ascii = render_command_line("ls -la", &[], Some(now - 30), false, 40, now);
-        let unicode = render_command_line("ls -lä", &[], Some(now - 30), false, 40, now);
+        let ascii = render_command_line(
+            &Theme::default(),
+            "ls -la",
+            &[],
+            Some(now - 30),
+            None,
+            false,
+            false,
+            40,
+            now,
+        );
+        let unicode = render_command_line(
+            &Theme::default(),
+            "ls -lä",
+            &[],
+            Some(now - 30),
+            None,
+            false,
+            false,
+            40,
+            now,
+        );
         assert_eq!(text_of(&ascii).width(), text_of(&unicode).width());
     }
 
     #[test]
     fn fits_short_commands_without_ellipsis() {
-        let line = render_command_line("ls", &[], None, false, 40, 0);
+        let line = render_command_line(
+            &Theme::default(),
+            "ls",
+            &[],
+            None,
+            None,
+            false,
+            false,
+            40,
+            0,
+        );
         assert!(!text_of(&line).contains("..."));
     }
+
+    #[test]
+    fn scrolls_the_query_to_keep_the_cursor_visible() {
+        assert_eq!(input_scroll("git push", 8, 20), (0, 0));
+        assert_eq!(input_scroll("git push", 8, 6), (2, 2));
+        // A wide glyph scrolls out whole.
+        assert_eq!(input_scroll("日本 ls", 7, 4), (6, 4));
+        // Text after the cursor never causes scrolling.
+        assert_eq!(input_scroll("git push origin", 3, 6), (0, 0));
+    }
+
+    #[test]
+    fn builds_the_help_bar_from_the_bindings() {
+        let text = |bindings: &Bindings| -> Vec<String> {
+            help_keys(bindings)
+                .into_iter()
+                .map(|(keys, label)| format!("{} {}", keys, label))
+                .collect()
+        };
+        assert_eq!(
+            text(&Bindings::emacs()),
+            [
+                "↓/↑ navigate",
+                "Enter select",
+                "Tab run",
+                "S-Tab mark",
+                "Ctrl+D hide",
+                "Ctrl+T mode",
+                "Ctrl+G scope",
+                "Esc cancel",
+            ]
+        );
+
+        let normal = text(&Bindings::vi_normal());
+        assert_eq!(normal[..2], ["j/k navigate", "gg/G top/bottom"]);
+        assert!(normal.contains(&"dd hide".to_string()));
+
+        let rebound = Bindings::emacs().with_overrides(&[
+            ("ctrl-x".parse().unwrap(), Action::Hide),
+            ("ctrl-d".parse().unwrap(), Action::Unbound),
+            ("ctrl-t".parse().unwrap(), Action::Unbound),
+        ]);
+        let rebound = text(&rebound);
+        assert!(rebound.contains(&"Ctrl+X hide".to_string()));
+        assert!(!rebound.iter().any(|entry| entry.ends_with(" mode")));
+    }
+
+    #[test]
+    fn drops_the_preview_when_short_of_rows() {
+        let tall = layout(Rect::new(0, 0, 80, 40), config::Layout::Default);
+        assert_eq!(tall.preview.map(|area| area.height), Some(PREVIEW_HEIGHT));
+        assert_eq!(
+            tall.results.height,
+            40 - INPUT_HEIGHT - PREVIEW_HEIGHT - HELP_HEIGHT
+        );
+
+        // Inline, ten rows below the prompt.
+        let short = layout(Rect::new(0, 30, 80, 10), config::Layout::Default);
+        assert_eq!(short.preview, None);
+        assert_eq!(short.results, Rect::new(0, 33, 80, 6));
+        assert_eq!(short.help, Rect::new(0, 39, 80, 1));
+    }
+
+    #[test]
+    fn marks_rows_in_the_prefix() {
+        let marked =
+            render_command_line(&Theme::default(), "ls", &[], None, None, false, true, 40, 0);
+        assert!(text_of(&marked).starts_with("● ls"));
+        let selected =
+            render_command_line(&Theme::default(), "ls", &[], None, None, true, false, 40, 0);
+        assert!(text_of(&selected).starts_with("> ls"));
+    }
+
+    #[test]
+    fn shows_the_source_tag_beside_the_time() {
+        let now = 1_700_000_000;
+        let line = render_command_line(
+            &Theme::default(),
+            "ls",
+            &[],
+            Some(now - 30),
+            Some("server"),
+            false,
+            false,
+            40,
+            now,
+        );
+        assert!(text_of(&line).ends_with("server  just now"));
+        let line = render_command_line(
+            &Theme::default(),
+            "ls",
+            &[],
+            None,
+            Some("server"),
+            false,
+            false,
+            40,
+            now,
+        );
+        assert!(text_of(&line).ends_with("server"));
+    }
+
+    /// The screen as text, one string per row with trailing blanks cut.
+    fn render_screen(layout: config::Layout, width: u16, height: u16) -> Vec<String> {
+        use ratatui::{backend::TestBackend, Terminal};
+
+        let entries: Vec<HistoryEntry> = ["git status", "git push", "cargo build"]
+            .iter()
+            .map(|command| HistoryEntry {
+                command: command.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let results: Vec<SearchResult> = (0..entries.len())
+            .map(|index| SearchResult {
+                index,
+                indices: Vec::new(),
+            })
+            .collect();
+
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal
+            .draw(|frame| {
+                UI::new().layout(layout).render(
+                    frame,
+                    &LineEditor::new("git".to_string()),
+                    "fuzzy",
+                    &entries,
+                    &results,
+                    &[],
+                    None,
+                    &Bindings::emacs(),
+                    "all",
+                    false,
+                    1,
+                    0,
+                    &mut ListState::default(),
+                    &mut 0,
+                    None,
+                );
+            })
+            .unwrap();
+
+        rows(terminal.backend().buffer())
+    }
+
+    fn rows(buffer: &ratatui::buffer::Buffer) -> Vec<String> {
+        let area = buffer.area;
+        (0..area.height)
+            .map(|y| {
+                let row: String = (0..area.width).map(|x| buffer[(x, y)].symbol()).collect();
+                row.trim_end().to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn wraps_the_preview_at_newlines_and_the_edge() {
+        let lines = highlighted_lines(&Theme::default(), "echo one\n\tgit push --force", 8);
+        let rows: Vec<String> = lines.iter().map(text_of).collect();
+        assert_eq!(rows, ["echo one", " git pus", "h --forc", "e"]);
+
+        let theme = Theme::default();
+        let styled = |text: &str| {
+            lines
+                .iter()
+                .flat_map(|line| &line.spans)
+                .find(|span| span.content == text)
+                .map(|span| span.style)
+        };
+        assert_eq!(
+            styled("echo"),
+            Some(theme.text.add_modifier(Modifier::BOLD))
+        );
+        assert_eq!(styled("--forc"), Some(theme.flag));
+
+        // A continued line breaks where the shell saw it break.
+        let lines = highlighted_lines(&theme, "make \\\n  install", 20);
+        let rows: Vec<String> = lines.iter().map(text_of).collect();
+        assert_eq!(rows, ["make \\", "  install"]);
+    }
+
+    #[test]
+    fn packs_details_into_rows() {
+        let items = ["2024-03-02 10:00", "run 3 times", "~/src"]
+            .map(Span::raw)
+            .to_vec();
+        let lines = pack(items, Span::raw(" · "), 32);
+        let rows: Vec<String> = lines.iter().map(text_of).collect();
+        assert_eq!(rows, ["2024-03-02 10:00 · run 3 times", "~/src"]);
+    }
+
+    #[test]
+    fn shortens_the_home_directory() {
+        let home = Path::new("/home/me");
+        assert_eq!(display_dir(Path::new("/home/me/src"), Some(home)), "~/src");
+        assert_eq!(display_dir(home, Some(home)), "~");
+        assert_eq!(display_dir(Path::new("/srv"), Some(home)), "/srv");
+    }
+
+    #[test]
+    fn scrolls_long_commands_above_the_details() {
+        use ratatui::{backend::TestBackend, Terminal};
+
+        let entry = HistoryEntry {
+            command: (1..=7)
+                .map(|n| format!("echo {}", n))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            cwd: Some("/srv/app".into()),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        let mut scroll = 10;
+        let mut terminal = Terminal::new(TestBackend::new(30, 8)).unwrap();
+        terminal
+            .draw(|frame| {
+                UI::new().render_preview(
+                    frame,
+                    frame.area(),
+                    &entry,
+                    &Bindings::emacs(),
+                    &mut scroll,
+                )
+            })
+            .unwrap();
+
+        // Scrolled no further than the last line.
+        assert_eq!(scroll, 2);
+        assert_eq!(
+            rows(terminal.backend().buffer()),
+            [
+                "┌ Preview ───────────────────┐",
+                "│echo 3                      │",
+                "│echo 4                      │",
+                "│echo 5                      │",
+                "│echo 6                      │",
+                "│echo 7                      │",
+                "│/srv/app · exit 1           │",
+                "└──────── 3–7 of 7 · S-↑/S-↓ ┘",
+            ]
+        );
+    }
+
+    #[test]
+    fn draws_the_input_above_the_results() {
+        assert_eq!(
+            render_screen(config::Layout::Default, 60, 20),
+            [
+                "┌ ihistory · fuzzy ────────────────────────────────────────┐",
+                "│> git                                                     │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ 3 results · all ─────────────────────────────────────────┐",
+                "│  git status                                              │",
+                "│> git push                                                │",
+                "│  cargo build                                             │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ Preview ─────────────────────────────────────────────────┐",
+                "│git push                                                  │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "└──────────────────────────────────────────────────────────┘",
+                "↓/↑ navigate  Enter select  Tab run  S-Tab mark  Ctrl+D hide",
+            ]
+        );
+    }
+
+    #[test]
+    fn lists_upwards_above_the_input_in_reverse() {
+        // The best match sits just above the input, closest to the prompt.
+        assert_eq!(
+            render_screen(config::Layout::Reverse, 60, 20),
+            [
+                "┌ Preview ─────────────────────────────────────────────────┐",
+                "│git push                                                  │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ 3 results · all ─────────────────────────────────────────┐",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "│  cargo build                                             │",
+                "│> git push                                                │",
+                "│  git status                                              │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ ihistory · fuzzy ────────────────────────────────────────┐",
+                "│> git                                                     │",
+                "└──────────────────────────────────────────────────────────┘",
+                "↓/↑ navigate  Enter select  Tab run  S-Tab mark  Ctrl+D hide",
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_the_list_order_with_the_input_at_the_bottom() {
+        assert_eq!(
+            render_screen(config::Layout::InputBottom, 60, 20),
+            [
+                "┌ 3 results · all ─────────────────────────────────────────┐",
+                "│  git status                                              │",
+                "│> git push                                                │",
+                "│  cargo build                                             │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ Preview ─────────────────────────────────────────────────┐",
+                "│git push                                                  │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "│                                                          │",
+                "└──────────────────────────────────────────────────────────┘",
+                "┌ ihistory · fuzzy ────────────────────────────────────────┐",
+                "│> git                                                     │",
+                "└──────────────────────────────────────────────────────────┘",
+                "↓/↑ navigate  Enter select  Tab run  S-Tab mark  Ctrl+D hide",
+            ]
+        );
+    }
+
+    #[test]
+    fn puts_the_preview_beside_the_results_when_wide() {
+        assert_eq!(
+            render_screen(config::Layout::PreviewRight, 100, 12),
+            [
+                "┌ ihistory · fuzzy ────────────────────────────────────────────────────────────────────────────────┐",
+                "│> git                                                                                             │",
+                "└──────────────────────────────────────────────────────────────────────────────────────────────────┘",
+                "┌ 3 results · all ────────────────────────────────────┐┌ Preview ──────────────────────────────────┐",
+                "│  git status                                         ││git push                                   │",
+                "│> git push                                           ││                                           │",
+                "│  cargo build                                        ││                                           │",
+                "│                                                     ││                                           │",
+                "│                                                     ││                                           │",
+                "│                                                     ││                                           │",
+                "└─────────────────────────────────────────────────────┘└───────────────────────────────────────────┘",
+                "↓/↑ navigate  Enter select  Tab run  S-Tab mark  Ctrl+D hide  Ctrl+T mode  Ctrl+G scope  Esc cancel",
+            ]
+        );
+    }
+
+    fn entry_with_runs(timestamps: &[i64]) -> HistoryEntry {
+        use ihistory::history::Occurrence;
+        HistoryEntry {
+            command: "make".to_string(),
+            timestamp: timestamps.first().copied(),
+            occurrences: timestamps
+                .iter()
+                .map(|&ts| Occurrence {
+                    timestamp: Some(ts),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summarises_runs() {
+        let now = 1_700_000_000;
+        let first = 1_600_000_000;
+        let entry = entry_with_runs(&[now - 300, now - 7200, first]);
+        let local: DateTime<Local> = DateTime::from_timestamp(first, 0).unwrap().into();
+        assert_eq!(
+            format_run_summary(&entry, now).unwrap(),
+            format!(
+                "run 3 times, first {}, last 5m ago",
+                local.format("%Y-%m-%d")
+            )
+        );
+    }
+
+    #[test]
+    fn summarises_single_runs() {
+        let now = 1_700_000_000;
+        let entry = entry_with_runs(&[now - 300]);
+        assert_eq!(
+            format_run_summary(&entry, now).unwrap(),
+            "run once, last 5m ago"
+        );
+        assert_eq!(format_run_summary(&HistoryEntry::default(), now), None);
+    }
 }